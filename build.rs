@@ -0,0 +1,23 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Build script: records the git commit and build time for GET /version
+fn main() {
+    // GIT_SHA can be passed in where .git isn't available (e.g. Docker builds)
+    let git_sha = std::env::var("GIT_SHA").ok().filter(|sha| !sha.trim().is_empty()).or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|sha| sha.trim().to_string())
+    });
+    let build_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+
+    println!("cargo:rustc-env=BUILD_GIT_SHA={}", git_sha.unwrap_or_else(|| "unknown".to_string()));
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp);
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
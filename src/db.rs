@@ -0,0 +1,151 @@
+use std::cell::Cell;
+
+use postgres::types::{BorrowToSql, ToSql};
+use postgres::{Client, CopyInWriter, Error, NoTls, Row, RowIter, Transaction};
+use r2d2::{Pool, PooledConnection, State};
+use r2d2_postgres::PostgresConnectionManager;
+
+type Manager = PostgresConnectionManager<NoTls>;
+
+// A pooled connection as handlers get it, counting every statement it runs
+pub type Connection = Counted<PooledConnection<Manager>>;
+
+thread_local! {
+    // Statements run on this thread since reset_query_count; a request is handled start to finish on one thread
+    static QUERY_COUNT: Cell<usize> = const { Cell::new(0) };
+}
+
+// Start counting the statements of a new request
+pub fn reset_query_count() {
+    QUERY_COUNT.with(|count| count.set(0));
+}
+
+// Statements run on this thread since the last reset_query_count
+pub fn query_count() -> usize {
+    QUERY_COUNT.with(Cell::get)
+}
+
+fn count_query() {
+    QUERY_COUNT.with(|count| count.set(count.get() + 1));
+}
+
+// Queries: the statement methods handlers run, shared by connections and their transactions
+pub trait Queries {
+    fn query(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, Error>;
+    fn query_one(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Row, Error>;
+    fn query_opt(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, Error>;
+    fn execute(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<u64, Error>;
+    fn batch_execute(&mut self, query: &str) -> Result<(), Error>;
+}
+
+// Implement Queries for a postgres type by calling Target's inherent methods on it
+macro_rules! delegate_queries {
+    ($type:ty => $target:ty) => {
+        impl Queries for $type {
+            fn query(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, Error> {
+                <$target>::query(self, query, params)
+            }
+            fn query_one(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Row, Error> {
+                <$target>::query_one(self, query, params)
+            }
+            fn query_opt(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, Error> {
+                <$target>::query_opt(self, query, params)
+            }
+            fn execute(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<u64, Error> {
+                <$target>::execute(self, query, params)
+            }
+            fn batch_execute(&mut self, query: &str) -> Result<(), Error> {
+                <$target>::batch_execute(self, query)
+            }
+        }
+    };
+}
+
+delegate_queries!(PooledConnection<Manager> => Client);
+delegate_queries!(Transaction<'_> => Transaction);
+
+// Counted: a connection or transaction whose statements add to this thread's query count, so
+// QUERY_BUDGET sees every query a request runs wherever the handler runs it
+pub struct Counted<C>(C);
+
+impl<C> Counted<C> {
+    pub fn new(inner: C) -> Counted<C> {
+        Counted(inner)
+    }
+}
+
+impl<C: Queries> Queries for Counted<C> {
+    fn query(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, Error> {
+        count_query();
+        self.0.query(query, params)
+    }
+
+    fn query_one(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Row, Error> {
+        count_query();
+        self.0.query_one(query, params)
+    }
+
+    fn query_opt(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, Error> {
+        count_query();
+        self.0.query_opt(query, params)
+    }
+
+    fn execute(&mut self, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<u64, Error> {
+        count_query();
+        self.0.execute(query, params)
+    }
+
+    fn batch_execute(&mut self, query: &str) -> Result<(), Error> {
+        count_query();
+        self.0.batch_execute(query)
+    }
+}
+
+impl Connection {
+    // BEGIN and COMMIT aren't counted; only the statements run inside the transaction are
+    pub fn transaction(&mut self) -> Result<Counted<Transaction<'_>>, Error> {
+        self.0.transaction().map(Counted)
+    }
+
+    pub fn query_raw<P, I>(&mut self, query: &str, params: I) -> Result<RowIter<'_>, Error>
+    where
+        P: BorrowToSql,
+        I: IntoIterator<Item = P>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        count_query();
+        self.0.query_raw(query, params)
+    }
+}
+
+impl Counted<Transaction<'_>> {
+    pub fn commit(self) -> Result<(), Error> {
+        self.0.commit()
+    }
+
+    pub fn copy_in(&mut self, query: &str) -> Result<CopyInWriter<'_>, Error> {
+        count_query();
+        self.0.copy_in(query)
+    }
+}
+
+// DbPool: the connection pool shared by all handlers, handing out counted connections
+pub struct DbPool(Pool<Manager>);
+
+impl DbPool {
+    pub fn new(pool: Pool<Manager>) -> DbPool {
+        DbPool(pool)
+    }
+
+    pub fn get(&self) -> Result<Connection, r2d2::Error> {
+        self.0.get().map(Counted::new)
+    }
+
+    pub fn state(&self) -> State {
+        self.0.state()
+    }
+
+    pub fn max_size(&self) -> u32 {
+        self.0.max_size()
+    }
+}
@@ -0,0 +1,34 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+// One open GET /articles/stream connection per sender; insert paths publish to all of them
+static SUBSCRIBERS: Mutex<Vec<Sender<ArticleEvent>>> = Mutex::new(Vec::new());
+
+// ArticleEvent: a newly created article, already serialized as one line of JSON
+#[derive(Clone)]
+pub struct ArticleEvent {
+    pub id: i32,
+    pub data: String,
+}
+
+// Register a new stream connection; its receiver gets every article published from now on
+pub fn subscribe() -> Receiver<ArticleEvent> {
+    let (sender, receiver) = mpsc::channel();
+    lock_subscribers().push(sender);
+    receiver
+}
+
+// Send an event to every subscriber, dropping the ones whose connection has gone away
+pub fn publish(event: ArticleEvent) {
+    lock_subscribers().retain(|subscriber| subscriber.send(event.clone()).is_ok());
+}
+
+// Number of open stream connections
+pub fn subscriber_count() -> usize {
+    lock_subscribers().len()
+}
+
+// A panic elsewhere while holding the lock leaves the list usable, so recover it instead of panicking too
+fn lock_subscribers() -> std::sync::MutexGuard<'static, Vec<Sender<ArticleEvent>>> {
+    SUBSCRIBERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
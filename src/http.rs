@@ -0,0 +1,164 @@
+use std::fmt;
+
+use crate::query::QueryParams;
+
+// Error for a request whose request line can't be read; handle_client answers these with a 400
+#[derive(Debug)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// HttpRequest: a raw request split once into its parts, so handlers don't re-scan the text.
+// Header names keep the case they were sent in; use header() to look one up.
+pub struct HttpRequest<'a> {
+    pub method: &'a str,
+    // Protocol version from the request line, e.g. "HTTP/1.1"
+    pub version: &'a str,
+    // Request target as sent, e.g. "/articles?limit=5"
+    pub target: &'a str,
+    // Target without the query string
+    pub path: &'a str,
+    // Path split on "/" without leading/trailing slashes, so "/articles/5/" is ["articles", "5"]
+    pub segments: Vec<&'a str>,
+    // Raw query string, empty when there is none
+    pub query_string: &'a str,
+    pub query: QueryParams,
+    pub headers: Vec<(&'a str, &'a str)>,
+    pub body: &'a str,
+}
+
+impl<'a> HttpRequest<'a> {
+    // Parse a raw request: the request line, headers up to the blank line, then the body
+    pub fn parse(raw: &'a str) -> Result<HttpRequest<'a>, ParseError> {
+        let (head, body) = raw.split_once("\r\n\r\n").unwrap_or((raw, ""));
+        let mut lines = head.lines();
+
+        let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+        let (method, target, version) = match (request_line.next(), request_line.next(), request_line.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/") => (method, target, version),
+            _ => return Err(ParseError("Malformed request line".to_string())),
+        };
+
+        let (path, query_string) = target.split_once('?').unwrap_or((target, ""));
+        let trimmed = path.strip_prefix('/').unwrap_or(path);
+        let trimmed = trimmed.strip_suffix('/').unwrap_or(trimmed);
+        let segments = if trimmed.is_empty() { Vec::new() } else { trimmed.split('/').collect() };
+
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+
+        Ok(HttpRequest {
+            method,
+            version,
+            target,
+            path,
+            segments,
+            query_string,
+            query: QueryParams::parse(query_string),
+            headers,
+            body,
+        })
+    }
+
+    // First value of a header, matching the name case-insensitively
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| *value)
+    }
+
+    // Every value of a header, in the order sent
+    pub fn header_values<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'a str> + 'b {
+        self.headers.iter().filter(move |(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| *value)
+    }
+
+    // The nth path segment ("/articles/5/move" has "articles" at 0 and "5" at 1), empty when absent
+    pub fn segment(&self, index: usize) -> &'a str {
+        self.segments.get(index).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_post_with_headers_and_body() {
+        let raw = "POST /articles?limit=5&source=a%20b HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nX-Tag: one\r\nx-tag: two\r\n\r\n{\"title\":\"t\"}";
+        let request = HttpRequest::parse(raw).unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.version, "HTTP/1.1");
+        assert_eq!(request.target, "/articles?limit=5&source=a%20b");
+        assert_eq!(request.path, "/articles");
+        assert_eq!(request.query_string, "limit=5&source=a%20b");
+        assert_eq!(request.query.get_i64("limit").unwrap(), Some(5));
+        assert_eq!(request.query.get_str("source"), Some("a b"));
+        assert_eq!(request.header("content-type"), Some("application/json"));
+        assert_eq!(request.header_values("X-TAG").collect::<Vec<_>>(), ["one", "two"]);
+        assert_eq!(request.header("Authorization"), None);
+        assert_eq!(request.body, "{\"title\":\"t\"}");
+    }
+
+    #[test]
+    fn body_keeps_everything_after_the_blank_line() {
+        let request = HttpRequest::parse("POST /articles HTTP/1.1\r\n\r\nline one\r\n\r\nline two").unwrap();
+        assert_eq!(request.body, "line one\r\n\r\nline two");
+        assert!(request.headers.is_empty());
+    }
+
+    #[test]
+    fn request_without_body_or_query() {
+        let request = HttpRequest::parse("GET /ping HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(request.path, "/ping");
+        assert_eq!(request.version, "HTTP/1.0");
+        assert_eq!(request.query_string, "");
+        assert_eq!(request.body, "");
+    }
+
+    #[test]
+    fn header_values_are_trimmed_and_lines_without_colon_skipped() {
+        let request = HttpRequest::parse("GET / HTTP/1.1\r\nAccept:   */*  \r\nnot a header\r\n\r\n").unwrap();
+        assert_eq!(request.headers, [("Accept", "*/*")]);
+    }
+
+    #[test]
+    fn malformed_request_lines_are_errors() {
+        for raw in ["", "\r\n\r\n", "GET\r\n\r\n", "GET /articles\r\n\r\n", "GET /articles FTP/1.0\r\n\r\n"] {
+            let error = HttpRequest::parse(raw).err().unwrap_or_else(|| panic!("{:?} should not parse", raw));
+            assert_eq!(error.to_string(), "Malformed request line");
+        }
+    }
+
+    #[test]
+    fn segments_drop_the_query_string() {
+        let request = HttpRequest::parse("GET /articles/5?include_raw=true HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.segments, ["articles", "5"]);
+        assert_eq!(request.segment(1), "5");
+    }
+
+    #[test]
+    fn trailing_slash_adds_no_segment() {
+        let request = HttpRequest::parse("GET /articles/5/ HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.segments, ["articles", "5"]);
+        assert!(HttpRequest::parse("GET / HTTP/1.1\r\n\r\n").unwrap().segments.is_empty());
+    }
+
+    #[test]
+    fn extra_segments_are_kept() {
+        let request = HttpRequest::parse("POST /articles/5/move/9/extra HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.segments, ["articles", "5", "move", "9", "extra"]);
+        assert_eq!(request.segment(3), "9");
+        assert_eq!(request.segment(7), "");
+    }
+
+    #[test]
+    fn empty_inner_segments_are_kept() {
+        let request = HttpRequest::parse("GET /articles//5 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.segments, ["articles", "", "5"]);
+    }
+}
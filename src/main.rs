@@ -1,284 +1,5213 @@
-use postgres::{Client, NoTls};
+use postgres::{Client, NoTls, Row};
+use postgres::Config as PgConfig;
+use postgres::error::SqlState;
+use postgres::binary_copy::BinaryCopyInWriter;
+use postgres::types::{ToSql, Type};
+use postgres::fallible_iterator::FallibleIterator;
 use postgres::Error as PostgresError;
-use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write};
+use r2d2::Pool;
+use r2d2_postgres::PostgresConnectionManager;
+use std::net::{IpAddr, Ipv6Addr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::env;
-use reqwest;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 use scraper::{Html, Selector};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, OsRng};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 
 #[macro_use]
 extern crate serde_derive;
 
-// Model: Article struct with id, title, content, source
+use serde::Serialize;
+
+mod db;
+mod events;
+mod http;
+mod query;
+mod scrapers;
+
+use db::{Connection, DbPool, Queries};
+use events::ArticleEvent;
+use http::HttpRequest;
+use query::{QueryError, QueryParams};
+use scrapers::{all_scrapers, find_scraper, Scraper, DEFAULT_SCRAPE_COUNT, MAX_SCRAPE_COUNT};
+
+// Model: Article struct with id, title, content, source and timestamps
 #[derive(Serialize, Deserialize)]
 struct Article {
     id: Option<i32>,
     title: String,
     content: String,
     source: String,
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    raw_html: Option<String>,
+    #[serde(skip_deserializing)]
+    position: Option<i32>,
+    image_url: Option<String>,
+    url: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(skip_deserializing)]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(skip_deserializing)]
+    updated_at: Option<DateTime<Utc>>,
+    #[serde(skip_deserializing)]
+    published_at: Option<DateTime<Utc>>,
 }
 
-// Constants
-const OK_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n";
-const NOT_FOUND: &str = "HTTP/1.1 404 NOT FOUND\r\n\r\n";
-const INTERNAL_SERVER_ERROR: &str = "HTTP/1.1 500 INTERNAL SERVER ERROR\r\n\r\n";
 
-// Main function
-fn main() {
-    // Set database
-    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    if let Err(e) = set_database(&db_url) {
-        println!("Error: {}", e);
-        return;
+// Request body for POST /articles/batch-get
+#[derive(Deserialize)]
+struct BatchGetRequest {
+    ids: Vec<i32>,
+}
+
+// Result of a scrape run, returned as the scrape response body
+#[derive(Serialize)]
+struct ScrapeSummary {
+    source: String,
+    fetched: usize,
+    inserted: usize,
+    skipped: usize,
+    // Rows of the source deleted by POST /scrape/:source/refresh
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replaced: Option<u64>,
+    // With ?incremental=true, the already-stored title the scrape stopped at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stopped_at: Option<String>,
+    duration_ms: u128,
+}
+
+// Request body for POST /scrape/test
+#[derive(Deserialize)]
+struct ScrapeTestRequest {
+    url: String,
+    selector: String,
+}
+
+// Keyword rule for AUTO_CATEGORIZE, stored in category_rules and managed through /admin/category-rules
+#[derive(Serialize, Deserialize)]
+struct CategoryRule {
+    #[serde(default)]
+    id: Option<i32>,
+    keyword: String,
+    category: String,
+}
+
+// Request body for POST /admin/export-backup; path is relative to BACKUP_DIR
+#[derive(Deserialize)]
+struct ExportBackupRequest {
+    path: String,
+}
+
+// Request body for POST /admin/purge-by-query; at least one filter field is required
+#[derive(Deserialize)]
+struct PurgeRequest {
+    source: Option<String>,
+    created_before: Option<DateTime<Utc>>,
+    title_like: Option<String>,
+    #[serde(default)]
+    confirm: bool,
+}
+
+// Request body for POST /articles/import-from-url
+#[derive(Deserialize)]
+struct ImportRequest {
+    url: String,
+}
+
+// Article list served by another instance: either a bare array or an { "items": [...] } envelope
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ImportPayload {
+    List(Vec<Article>),
+    Envelope { items: Vec<Article> },
+}
+
+// One RFC 6902 JSON Patch operation
+#[derive(Deserialize)]
+struct PatchOperation {
+    op: String,
+    path: String,
+    value: Option<serde_json::Value>,
+}
+
+// Partial article sent as a plain JSON PATCH body; only the fields present are changed
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ArticleChanges {
+    title: Option<String>,
+    content: Option<String>,
+    source: Option<String>,
+}
+
+// Body of a PATCH request, chosen by its Content-Type
+enum PatchDocument {
+    JsonPatch(Vec<PatchOperation>),
+    Changes(ArticleChanges),
+}
+
+// Query options controlling how articles are rendered in GET responses
+struct ViewOptions {
+    include_raw: bool,
+    omit_nulls: bool,
+    normalize_ws: bool,
+}
+
+impl ViewOptions {
+    fn from_query(params: &QueryParams) -> Result<ViewOptions, QueryError> {
+        Ok(ViewOptions {
+            include_raw: params.get_flag("include_raw")?,
+            omit_nulls: params.get_flag("omit_nulls")?,
+            normalize_ws: params.get_flag("normalize_ws")?,
+        })
     }
+}
 
-    // Start server and print port
-    let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
-    println!("Server started at port 8080");
+// Query options accepted by GET /articles
+struct ListOptions {
+    view: ViewOptions,
+    include_hidden: bool,
+    ordered: bool,
+    distinct_titles: bool,
+    limit: i64,
+    offset: i64,
+    // ?after=<id> pages by id instead of offset; negative values start from the beginning
+    after: Option<i32>,
+    source: Option<String>,
+    category: Option<String>,
+    // Bounds on the content length in characters, both inclusive
+    min_content_len: Option<i64>,
+    max_content_len: Option<i64>,
+    facets: Vec<String>,
+    // ?sort=<column>&order=asc|desc; None keeps the default order
+    sort: Option<(&'static str, &'static str)>,
+}
 
-    // Handle the client
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                handle_client(stream, &db_url);
-            }
-            Err(e) => {
-                println!("Error: {}", e);
-            }
-        }
+impl ListOptions {
+    fn from_query(params: &QueryParams) -> Result<ListOptions, QueryError> {
+        Ok(ListOptions {
+            view: ViewOptions::from_query(params)?,
+            include_hidden: params.get_flag("include_hidden")?,
+            ordered: params.get_flag("ordered")?,
+            distinct_titles: params.get_flag("distinct_titles")?,
+            // Pagination is lenient: missing, malformed or negative values fall back to the defaults
+            limit: params
+                .get_i64("limit")
+                .ok()
+                .flatten()
+                .filter(|&limit| limit > 0)
+                .map_or(LIST_DEFAULT_LIMIT, |limit| limit.min(LIST_MAX_LIMIT)),
+            offset: params.get_i64("offset").ok().flatten().filter(|&offset| offset >= 0).unwrap_or(0),
+            after: params.get_i64("after")?.map(|after| after.clamp(0, i32::MAX as i64) as i32),
+            source: params.get_str("source").filter(|source| !source.trim().is_empty()).map(str::to_string),
+            category: params.get_str("category").filter(|category| !category.trim().is_empty()).map(str::to_string),
+            min_content_len: params.get_i64("min_content_len")?,
+            max_content_len: params.get_i64("max_content_len")?,
+            facets: params.get_list("facets"),
+            // Like pagination, sorting is lenient: an unknown column falls back to the default order and
+            // an unknown direction to ascending, so only allowlisted names ever reach the SQL
+            sort: params.get_str("sort").and_then(|sort| LIST_SORT_COLUMNS.iter().copied().find(|column| *column == sort.trim())).map(|column| {
+                let order = params.get_str("order").map(|order| order.trim().to_ascii_lowercase());
+                (column, if order.as_deref() == Some("desc") { "DESC" } else { "ASC" })
+            }),
+        })
     }
 }
 
-// Handle client function
-fn handle_client(mut stream: TcpStream, db_url: &str) {
-    let mut buffer = [0; 1024];
-    let mut request = String::new();
+// Query options accepted by POST /scrape/:source and /scrape/:source/refresh
+struct ScrapeOptions {
+    count: usize,
+    store_raw: bool,
+    // Delete the source's existing rows in the same transaction as the insert
+    refresh: bool,
+    // Stop at the first list item that is already stored, for scrapes that only pick up what's new
+    incremental: bool,
+}
 
-    match stream.read(&mut buffer) {
-        Ok(size) => {
-            request.push_str(String::from_utf8_lossy(&buffer[..size]).as_ref());
+impl ScrapeOptions {
+    fn from_query(params: &QueryParams, refresh: bool) -> Result<ScrapeOptions, QueryError> {
+        Ok(ScrapeOptions {
+            // ?count picks how many list items to take, clamped to 1..=MAX_SCRAPE_COUNT
+            count: params
+                .get_i64("count")?
+                .map_or(DEFAULT_SCRAPE_COUNT, |count| count.clamp(1, MAX_SCRAPE_COUNT as i64) as usize),
+            store_raw: params.get_flag("store_raw")?,
+            refresh,
+            incremental: params.get_flag("incremental")?,
+        })
+    }
+}
 
-            let (status_line, content) = match &*request {
-                r if r.starts_with("POST /articles") => handle_post_request(r, db_url),
-                r if r.starts_with("GET /articles/") => handle_get_request(r, db_url),
-                r if r.starts_with("GET /articles") => handle_get_all_request(r, db_url),
-                r if r.starts_with("PUT /articles/") => handle_put_request(r, db_url),
-                r if r.starts_with("DELETE /articles/") => handle_delete_request(r, db_url),
-                r if r.starts_with("POST /scrape/imdb") => handle_scrape_imdb(db_url),
-                r if r.starts_with("DELETE /scrape/source/imdb") => handle_delete_by_source(db_url, "imdb"),
-                _ => (NOT_FOUND.to_string(), "404 Not Found".to_string()),
-            };
+impl Default for ScrapeOptions {
+    fn default() -> ScrapeOptions {
+        ScrapeOptions { count: DEFAULT_SCRAPE_COUNT, store_raw: false, refresh: false, incremental: false }
+    }
+}
 
-            stream.write_all(format!("{}{}", status_line, content).as_bytes()).unwrap();
-        }
-        Err(e) => {
-            println!("Error: {}", e);
+// ServerTiming: time spent in each phase of a request (db, serialize), reported in the Server-Timing
+// header together with the total; handlers only record the phases they time
+#[derive(Default)]
+struct ServerTiming {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl ServerTiming {
+    // Run f and add its duration to the named phase
+    fn record<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let value = f();
+        let elapsed = start.elapsed();
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((phase, elapsed)),
         }
+        value
+    }
+
+    // Header value like "db;dur=1.204, serialize;dur=0.031, total;dur=1.390" in milliseconds
+    fn header_value(&self, total: Duration) -> String {
+        self.phases
+            .iter()
+            .chain([("total", total)].iter())
+            .map(|(name, duration)| format!("{};dur={:.3}", name, duration.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ")
     }
 }
 
-// Controllers
+// Looks up a configuration variable; the process environment outside of tests
+type EnvLookup<'a> = &'a dyn Fn(&str) -> Result<String, env::VarError>;
 
-// Handle POST request function
-fn handle_post_request(request: &str, db_url: &str) -> (String, String) {
-    match (get_article_request_body(request), Client::connect(db_url, NoTls)) {
-        (Ok(article), Ok(mut client)) => {
-            client
-                .execute(
-                    "INSERT INTO articles (title, content, source) VALUES ($1, $2, $3)",
-                    &[&article.title, &article.content, &article.source]
-                )
-                .unwrap();
+// AppState: config, the connection pool and the mutable state shared across requests
+struct AppState {
+    config: Config,
+    db: DbPool,
+    scrape_last_run: Mutex<HashMap<String, Instant>>,
+    // Connections currently being handled (one thread each) and accepted since startup
+    active_connections: AtomicUsize,
+    total_connections: AtomicU64,
+    // Set by SIGINT/SIGTERM; the accept loop stops and in-flight requests are allowed to finish
+    shutting_down: AtomicBool,
+    // Starts from READ_ONLY and is switched at runtime with POST /admin/read-only; writes get a 503 while set
+    read_only: AtomicBool,
+    // Background exports started with ?async=true, by job id
+    export_jobs: Mutex<HashMap<u64, ExportJob>>,
+    next_export_id: AtomicU64,
+    // Request budget per client IP when RATE_LIMIT_PER_MINUTE is set
+    rate_limits: Mutex<RateLimits>,
+    // POST /articles responses by hashed Idempotency-Key, when IDEMPOTENCY_KEYS is on
+    idempotency_keys: Mutex<HashMap<u64, IdempotentResponse>>,
+    // Scrapes and exports running now, capped by MAX_CONCURRENT_SCRAPES and MAX_CONCURRENT_EXPORTS
+    scrape_slots: Arc<RouteSlots>,
+    export_slots: Arc<RouteSlots>,
+}
 
-            (OK_RESPONSE.to_string(), "Article created".to_string())
+impl AppState {
+    fn new(config: Config, db: DbPool) -> AppState {
+        AppState {
+            read_only: AtomicBool::new(config.read_only),
+            scrape_slots: RouteSlots::new(config.max_concurrent_scrapes),
+            export_slots: RouteSlots::new(config.max_concurrent_exports),
+            config,
+            db,
+            scrape_last_run: Mutex::new(HashMap::new()),
+            active_connections: AtomicUsize::new(0),
+            total_connections: AtomicU64::new(0),
+            shutting_down: AtomicBool::new(false),
+            export_jobs: Mutex::new(HashMap::new()),
+            next_export_id: AtomicU64::new(1),
+            rate_limits: Mutex::new(RateLimits { buckets: HashMap::new(), swept: Instant::now() }),
+            idempotency_keys: Mutex::new(HashMap::new()),
         }
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error".to_string()),
     }
 }
 
-// Handle GET request function
-fn handle_get_request(request: &str, db_url: &str) -> (String, String) {
-    match (get_id(request).parse::<i32>(), Client::connect(db_url, NoTls)) {
-        (Ok(id), Ok(mut client)) =>
-            match client.query_one("SELECT * FROM articles WHERE id = $1", &[&id]) {
-                Ok(row) => {
-                    let article = Article {
-                        id: row.get(0),
-                        title: row.get(1),
-                        content: row.get(2),
-                        source: row.get(3),
-                    };
+// RouteSlots: how many requests to a heavy route are running, and how many may run at once (no cap when None)
+struct RouteSlots {
+    limit: Option<usize>,
+    running: AtomicUsize,
+}
 
-                    (OK_RESPONSE.to_string(), serde_json::to_string(&article).unwrap())
-                }
-                _ => (NOT_FOUND.to_string(), "Article not found".to_string()),
-            }
+impl RouteSlots {
+    fn new(limit: Option<usize>) -> Arc<RouteSlots> {
+        Arc::new(RouteSlots { limit, running: AtomicUsize::new(0) })
+    }
+
+    // Take a slot, or None when all of them are in use; the slot is given back when the RouteSlot drops
+    fn try_claim(self: &Arc<Self>) -> Option<RouteSlot> {
+        self.running
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| match self.limit {
+                Some(limit) if running >= limit => None,
+                _ => Some(running + 1),
+            })
+            .ok()
+            .map(|_| RouteSlot(Arc::clone(self)))
+    }
 
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error".to_string()),
+    // The 503 for a request that found every slot in use
+    fn busy_response(&self, route: &str) -> (String, String) {
+        error_response(
+            &with_header(SERVICE_UNAVAILABLE, "Retry-After", "1"),
+            &format!("Too many {} running (limit {}); retry shortly", route, self.limit.unwrap_or_default()),
+        )
     }
 }
 
-// Handle GET all request function
-fn handle_get_all_request(_request: &str, db_url: &str) -> (String, String) {
-    match Client::connect(db_url, NoTls) {
-        Ok(mut client) => {
-            let mut articles = Vec::new();
-
-            for row in client.query("SELECT * FROM articles", &[]).unwrap() {
-                articles.push(Article {
-                    id: row.get(0),
-                    title: row.get(1),
-                    content: row.get(2),
-                    source: row.get(3),
-                });
-            }
+// RouteSlot: one running request's claim on its route's RouteSlots
+struct RouteSlot(Arc<RouteSlots>);
+
+impl Drop for RouteSlot {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// RateLimits: the token buckets by client (see rate_limit_key), holding at most RATE_LIMIT_MAX_CLIENTS,
+// and when idle ones were last swept out
+struct RateLimits {
+    buckets: HashMap<IpAddr, RateBucket>,
+    swept: Instant,
+}
+
+// RateBucket: a token bucket holding up to RATE_LIMIT_PER_MINUTE tokens and refilling at that rate;
+// each request takes one
+struct RateBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateBucket {
+    // Refill for the time since the last request, then take one token, or return the seconds until
+    // the next one is available
+    fn take(&mut self, limit: f64, now: Instant) -> Result<(), u64> {
+        let refill_per_sec = limit / 60.0;
+        self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * refill_per_sec).min(limit);
+        self.updated = now;
 
-            (OK_RESPONSE.to_string(), serde_json::to_string(&articles).unwrap())
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - self.tokens) / refill_per_sec).ceil() as u64)
         }
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error".to_string()),
     }
 }
 
-// Handle PUT request function
-fn handle_put_request(request: &str, db_url: &str) -> (String, String) {
-    match (
-        get_id(request).parse::<i32>(),
-        get_article_request_body(request),
-        Client::connect(db_url, NoTls),
-    ) {
-        (Ok(id), Ok(article), Ok(mut client)) => {
-            client
-                .execute(
-                    "UPDATE articles SET title = $1, content = $2, source = $3 WHERE id = $4",
-                    &[&article.title, &article.content, &article.source, &id]
-                )
-                .unwrap();
+// IdempotentResponse: the answer to the first POST sent with an Idempotency-Key, replayed to retries
+// for IDEMPOTENCY_TTL_SECS; response is None while that first request is still being handled
+struct IdempotentResponse {
+    body_hash: u64,
+    stored: Instant,
+    response: Option<(String, String)>,
+}
+
+// ExportJob: a background export and, once it finishes, the temp file holding it
+struct ExportJob {
+    started: Instant,
+    status: ExportStatus,
+}
+
+enum ExportStatus {
+    Running,
+    Ready(PathBuf),
+    Failed,
+}
 
-            (OK_RESPONSE.to_string(), "Article updated".to_string())
+// Config: settings read from the environment at startup
+struct Config {
+    bind_addr: String,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    rate_limit_per_minute: Option<u32>,
+    idempotency_keys: bool,
+    idempotency_ttl_secs: u64,
+    port: u16,
+    db: PgConfig,
+    normalize_source: bool,
+    max_articles: Option<i64>,
+    cap_policy: CapPolicy,
+    search_order: SearchOrder,
+    batch_get_max_ids: usize,
+    scrape_allowed_hosts: Vec<String>,
+    raw_html_max_bytes: usize,
+    content_cipher: Option<Aes256Gcm>,
+    compress_content: bool,
+    gzip_responses: bool,
+    etags: bool,
+    gzip_min_bytes: usize,
+    version_endpoint: bool,
+    read_only: bool,
+    webhook_url: Option<String>,
+    webhook_batch: bool,
+    article_stream: bool,
+    sse_keepalive_secs: u64,
+    hidden_sources: Vec<String>,
+    allowed_sources: Vec<String>,
+    scrape_cooldown_secs: u64,
+    scrape_cooldowns: HashMap<String, u64>,
+    missing_as_null: bool,
+    db_pool_size: u32,
+    db_max_lifetime_secs: Option<u64>,
+    db_connect_attempts: u32,
+    skip_migrations: bool,
+    canonical_redirects: bool,
+    max_header_bytes: usize,
+    max_body_bytes: usize,
+    max_query_params: usize,
+    article_images: bool,
+    api_key: Option<String>,
+    dedup_case_insensitive: bool,
+    dedup_by_url: bool,
+    url_tracking_params: Vec<String>,
+    pagination_links: bool,
+    keyset_pagination: bool,
+    stream_list: bool,
+    keep_alive: bool,
+    keep_alive_timeout_secs: u64,
+    keep_alive_max_requests: usize,
+    read_timeout_secs: u64,
+    shutdown_timeout_secs: u64,
+    auto_categorize: bool,
+    geojson_export: bool,
+    since_feed: bool,
+    source_feeds: bool,
+    put_not_modified: bool,
+    debug_endpoints: bool,
+    scrape_all: bool,
+    scrape_min_title_len: usize,
+    scrape_timeout_secs: u64,
+    oembed_provider_name: Option<String>,
+    bulk_copy_threshold: usize,
+    title_max_len: usize,
+    content_max_len: usize,
+    article_schema: bool,
+    server_timing: bool,
+    query_budget: Option<usize>,
+    log_timings: bool,
+    list_facets: bool,
+    list_newest_first: bool,
+    error_negotiation: bool,
+    bulk_partial_mode: bool,
+    deprecation_headers: bool,
+    legacy_sunset: Option<String>,
+    cors_allow_origin: String,
+    scrape_date_selector: Option<String>,
+    scrape_date_format: Option<String>,
+    backup_dir: Option<PathBuf>,
+    async_exports: bool,
+    export_ttl_secs: u64,
+    max_concurrent_scrapes: Option<usize>,
+    max_concurrent_exports: Option<usize>,
+}
+
+// What to do when an insert would push the table past MAX_ARTICLES
+#[derive(PartialEq)]
+enum CapPolicy {
+    Reject,
+    EvictOldest,
+}
+
+// Primary sort of GET /articles/search; newer articles break ties either way
+#[derive(Clone, Copy)]
+enum SearchOrder {
+    // Newest first
+    Recency,
+    // Title matches ahead of content-only matches. There is no ranked score: content may be encrypted or
+    // compressed, so a tsvector over it can't be built, and this is title-match-first ordering only
+    Relevance,
+}
+
+impl SearchOrder {
+    fn parse(value: &str) -> Option<SearchOrder> {
+        match value {
+            "recency" => Some(SearchOrder::Recency),
+            "relevance" => Some(SearchOrder::Relevance),
+            _ => None,
         }
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error".to_string()),
     }
 }
 
-// Handle DELETE request function
-fn handle_delete_request(request: &str, db_url: &str) -> (String, String) {
-    match (get_id(request).parse::<i32>(), Client::connect(db_url, NoTls)) {
-        (Ok(id), Ok(mut client)) => {
-            let rows_affected = client.execute("DELETE FROM articles WHERE id = $1", &[&id]).unwrap();
+impl Config {
+    fn from_env() -> Config {
+        Config::from_vars(&|name| env::var(name))
+    }
 
-            if rows_affected == 0 {
-                return (NOT_FOUND.to_string(), "Article not found".to_string());
-            }
+    // Defaults for every setting apart from the database, without reading the environment
+    #[cfg(test)]
+    fn for_tests() -> Config {
+        Config::from_vars(&|name| match name {
+            "DATABASE_URL" => Ok("postgres://postgres@localhost/newsapi_test".to_string()),
+            _ => Err(env::VarError::NotPresent),
+        })
+    }
 
-            (OK_RESPONSE.to_string(), "Article deleted".to_string())
+    // Build the config from the variables var looks up; from_env passes the process environment
+    fn from_vars(var: EnvLookup) -> Config {
+        Config {
+            bind_addr: var("BIND_ADDR").ok().filter(|addr| !addr.trim().is_empty()).unwrap_or_else(|| "0.0.0.0".to_string()),
+            tls_cert: var("TLS_CERT").ok().filter(|path| !path.trim().is_empty()),
+            tls_key: var("TLS_KEY").ok().filter(|path| !path.trim().is_empty()),
+            rate_limit_per_minute: var("RATE_LIMIT_PER_MINUTE").ok().and_then(|v| v.trim().parse().ok()).filter(|&limit| limit > 0),
+            idempotency_keys: env_flag(var, "IDEMPOTENCY_KEYS", true),
+            idempotency_ttl_secs: var("IDEMPOTENCY_TTL_SECS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(86_400).max(1),
+            port: var("PORT").map_or(8080, |port| {
+                port.trim().parse().unwrap_or_else(|_| {
+                    log::error!("PORT must be a port number between 0 and 65535, got {:?}", port);
+                    process::exit(1);
+                })
+            }),
+            db: database_config(var).unwrap_or_else(|message| {
+                log::error!("{}", message);
+                process::exit(1);
+            }),
+            normalize_source: env_flag(var, "NORMALIZE_SOURCE", true),
+            max_articles: var("MAX_ARTICLES").ok().and_then(|v| v.trim().parse().ok()),
+            cap_policy: match var("MAX_ARTICLES_POLICY").as_deref() {
+                Ok("evict") | Ok("evict_oldest") => CapPolicy::EvictOldest,
+                _ => CapPolicy::Reject,
+            },
+            search_order: var("SEARCH_ORDER").ok().and_then(|v| SearchOrder::parse(v.trim())).unwrap_or(SearchOrder::Recency),
+            batch_get_max_ids: var("BATCH_GET_MAX_IDS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(1000),
+            scrape_allowed_hosts: var("SCRAPE_ALLOWED_HOSTS")
+                .unwrap_or_else(|_| "www.imdb.com,imdb.com".to_string())
+                .split(',')
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            raw_html_max_bytes: var("RAW_HTML_MAX_BYTES").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(1_000_000),
+            content_cipher: var("ENCRYPTION_KEY").ok().map(|key| {
+                let key = BASE64.decode(key.trim()).unwrap_or_else(|_| {
+                    log::error!("ENCRYPTION_KEY must be base64");
+                    process::exit(1);
+                });
+                Aes256Gcm::new_from_slice(&key).unwrap_or_else(|_| {
+                    log::error!("ENCRYPTION_KEY must decode to 32 bytes, got {}", key.len());
+                    process::exit(1);
+                })
+            }),
+            compress_content: env_flag(var, "COMPRESS_CONTENT", false),
+            gzip_responses: env_flag(var, "GZIP_RESPONSES", true),
+            etags: env_flag(var, "ETAGS", true),
+            gzip_min_bytes: var("GZIP_MIN_BYTES").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(1024),
+            version_endpoint: env_flag(var, "VERSION_ENDPOINT", true),
+            read_only: env_flag(var, "READ_ONLY", false),
+            webhook_url: var("WEBHOOK_URL").ok().filter(|url| !url.trim().is_empty()),
+            webhook_batch: env_flag(var, "WEBHOOK_BATCH", false),
+            article_stream: env_flag(var, "ARTICLE_STREAM", true),
+            sse_keepalive_secs: var("SSE_KEEPALIVE_SECS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(15).max(1),
+            hidden_sources: var("HIDDEN_SOURCES")
+                .unwrap_or_default()
+                .split(',')
+                .map(normalize_source)
+                .filter(|source| !source.is_empty())
+                .collect(),
+            // Compared case-insensitively; scraped articles use their scraper's source and skip the check
+            allowed_sources: var("ALLOWED_SOURCES")
+                .unwrap_or_default()
+                .split(',')
+                .map(normalize_source)
+                .filter(|source| !source.is_empty())
+                .collect(),
+            scrape_cooldown_secs: var("SCRAPE_COOLDOWN_SECS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(0),
+            scrape_cooldowns: var("SCRAPE_COOLDOWNS")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|entry| entry.split_once('='))
+                .filter_map(|(source, secs)| Some((normalize_source(source), secs.trim().parse().ok()?)))
+                .collect(),
+            missing_as_null: env_flag(var, "MISSING_AS_NULL", false),
+            db_pool_size: var("DB_POOL_SIZE").ok().and_then(|v| v.trim().parse().ok()).filter(|&size| size > 0).unwrap_or(10),
+            // 0 keeps connections for as long as they work
+            db_max_lifetime_secs: match var("DB_MAX_LIFETIME_SECS").ok().and_then(|v| v.trim().parse().ok()) {
+                Some(0) => None,
+                Some(secs) => Some(secs),
+                None => Some(1800),
+            },
+            db_connect_attempts: var("DB_CONNECT_ATTEMPTS").ok().and_then(|v| v.trim().parse().ok()).filter(|&attempts| attempts > 0).unwrap_or(5),
+            skip_migrations: env_flag(var, "SKIP_MIGRATIONS", false),
+            canonical_redirects: env_flag(var, "CANONICAL_REDIRECTS", true),
+            max_header_bytes: var("MAX_HEADER_BYTES").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(8192),
+            max_body_bytes: var("MAX_BODY_BYTES").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(1_048_576),
+            max_query_params: var("MAX_QUERY_PARAMS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(50),
+            article_images: env_flag(var, "ARTICLE_IMAGES", true),
+            api_key: var("API_KEY").ok().filter(|key| !key.trim().is_empty()),
+            dedup_case_insensitive: env_flag(var, "DEDUP_CASE_INSENSITIVE", false),
+            dedup_by_url: env_flag(var, "DEDUP_BY_URL", false),
+            url_tracking_params: var("URL_TRACKING_PARAMS")
+                .unwrap_or_else(|_| "utm_*,fbclid,gclid,ref_".to_string())
+                .split(',')
+                .map(|param| param.trim().to_lowercase())
+                .filter(|param| !param.is_empty())
+                .collect(),
+            pagination_links: env_flag(var, "PAGINATION_LINKS", true),
+            keyset_pagination: env_flag(var, "KEYSET_PAGINATION", true),
+            stream_list: env_flag(var, "STREAM_LIST", false),
+            keep_alive: env_flag(var, "KEEP_ALIVE", true),
+            keep_alive_timeout_secs: var("KEEP_ALIVE_TIMEOUT_SECS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(5).max(1),
+            keep_alive_max_requests: var("KEEP_ALIVE_MAX_REQUESTS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(100).max(1),
+            read_timeout_secs: var("READ_TIMEOUT_SECS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(30).max(1),
+            shutdown_timeout_secs: var("SHUTDOWN_TIMEOUT_SECS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(30),
+            auto_categorize: env_flag(var, "AUTO_CATEGORIZE", false),
+            geojson_export: env_flag(var, "GEOJSON_EXPORT", true),
+            since_feed: env_flag(var, "SINCE_FEED", true),
+            source_feeds: env_flag(var, "SOURCE_FEEDS", true),
+            put_not_modified: env_flag(var, "PUT_NOT_MODIFIED", false),
+            debug_endpoints: env_flag(var, "DEBUG_ENDPOINTS", false),
+            scrape_all: env_flag(var, "SCRAPE_ALL", true),
+            oembed_provider_name: match var("OEMBED_PROVIDER_NAME") {
+                Ok(name) if name.trim().is_empty() => None,
+                Ok(name) => Some(name.trim().to_string()),
+                Err(_) => Some("newsapi".to_string()),
+            },
+            title_max_len: var("TITLE_MAX_LEN").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(300),
+            content_max_len: var("CONTENT_MAX_LEN").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(100_000),
+            article_schema: env_flag(var, "ARTICLE_SCHEMA", true),
+            server_timing: env_flag(var, "SERVER_TIMING", false),
+            query_budget: var("QUERY_BUDGET").ok().and_then(|v| v.trim().parse().ok()),
+            log_timings: env_flag(var, "LOG_TIMINGS", false),
+            list_facets: env_flag(var, "LIST_FACETS", true),
+            list_newest_first: env_flag(var, "LIST_NEWEST_FIRST", true),
+            error_negotiation: env_flag(var, "ERROR_NEGOTIATION", true),
+            bulk_partial_mode: env_flag(var, "BULK_PARTIAL_MODE", true),
+            deprecation_headers: env_flag(var, "DEPRECATION_HEADERS", true),
+            legacy_sunset: var("LEGACY_SUNSET").ok().filter(|date| !date.trim().is_empty()).and_then(|date| parse_sunset(&date)),
+            bulk_copy_threshold: var("BULK_COPY_THRESHOLD").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(1000),
+            // Blank titles are never stored, so the minimum is at least 1
+            scrape_min_title_len: var("SCRAPE_MIN_TITLE_LEN").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(2).max(1),
+            scrape_timeout_secs: var("SCRAPE_TIMEOUT_SECS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(15),
+            cors_allow_origin: var("CORS_ALLOW_ORIGIN").ok().filter(|origin| !origin.trim().is_empty()).unwrap_or_else(|| "*".to_string()),
+            scrape_date_selector: var("SCRAPE_DATE_SELECTOR").ok().filter(|selector| !selector.trim().is_empty()),
+            scrape_date_format: var("SCRAPE_DATE_FORMAT").ok().filter(|format| !format.trim().is_empty()),
+            backup_dir: var("BACKUP_DIR").ok().filter(|dir| !dir.trim().is_empty()).map(PathBuf::from),
+            async_exports: env_flag(var, "ASYNC_EXPORTS", true),
+            export_ttl_secs: var("EXPORT_TTL_SECS").ok().and_then(|v| v.trim().parse().ok()).unwrap_or(3600),
+            max_concurrent_scrapes: var("MAX_CONCURRENT_SCRAPES").ok().and_then(|v| v.trim().parse().ok()).filter(|&limit| limit > 0),
+            max_concurrent_exports: var("MAX_CONCURRENT_EXPORTS").ok().and_then(|v| v.trim().parse().ok()).filter(|&limit| limit > 0),
         }
-        _ => (INTERNAL_SERVER_ERROR.to_string(), "Error".to_string()),
+    }
+
+    // Minimum interval between scrapes of a source: SCRAPE_COOLDOWNS entry, else SCRAPE_COOLDOWN_SECS
+    fn scrape_cooldown(&self, source: &str) -> Duration {
+        Duration::from_secs(*self.scrape_cooldowns.get(source).unwrap_or(&self.scrape_cooldown_secs))
     }
 }
 
-// Handle scrape IMDb function
-fn handle_scrape_imdb(db_url: &str) -> (String, String) {
-    println!("Starting IMDb scrape...");
+// Build the Postgres config from DATABASE_URL, or from the libpq-style PG* variables when it is unset
+fn database_config(var: EnvLookup) -> Result<PgConfig, String> {
+    if let Ok(url) = var("DATABASE_URL") {
+        return url.parse().map_err(|e| format!("DATABASE_URL must be a valid connection string: {}", e));
+    }
 
-    let client = reqwest::blocking::Client::new();
-    let response = match client.get("https://www.imdb.com/search/title/?groups=top_100&sort=user_rating,desc&count=10")
-        .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/58.0.3029.110 Safari/537.3")
-        .send() {
-        Ok(res) => {
-            println!("Received response from IMDb");
-            match res.text() {
-                Ok(text) => text,
-                Err(e) => {
-                    println!("Error reading response text: {}", e);
-                    return (INTERNAL_SERVER_ERROR.to_string(), "Error reading response text".to_string());
-                },
-            }
-        },
+    let mut db = PgConfig::new();
+    db.host(&var("PGHOST").map_err(|_| "DATABASE_URL or PGHOST must be set".to_string())?);
+
+    if let Ok(port) = var("PGPORT") {
+        db.port(port.trim().parse().map_err(|_| format!("PGPORT must be a valid port number, got {:?}", port))?);
+    }
+    if let Ok(user) = var("PGUSER") {
+        db.user(&user);
+    }
+    if let Ok(password) = var("PGPASSWORD") {
+        db.password(password);
+    }
+    if let Ok(dbname) = var("PGDATABASE") {
+        db.dbname(&dbname);
+    }
+
+    Ok(db)
+}
+
+// Constants
+const OK_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n";
+const CREATED_RESPONSE: &str = "HTTP/1.1 201 CREATED\r\nContent-Type: application/json\r\n\r\n";
+const RSS_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: application/rss+xml; charset=utf-8\r\n\r\n";
+const ACCEPTED: &str = "HTTP/1.1 202 ACCEPTED\r\nContent-Type: application/json\r\n\r\n";
+const OK_TEXT_RESPONSE: &str = "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\n";
+const NO_CONTENT: &str = "HTTP/1.1 204 NO CONTENT\r\n\r\n";
+const NOT_MODIFIED: &str = "HTTP/1.1 304 NOT MODIFIED\r\n\r\n";
+const SERVER_OPTIONS_RESPONSE: &str = "HTTP/1.1 204 NO CONTENT\r\nAllow: GET, POST, PUT, PATCH, DELETE, OPTIONS\r\n\r\n";
+const BAD_REQUEST: &str = "HTTP/1.1 400 BAD REQUEST\r\n\r\n";
+const UNAUTHORIZED: &str = "HTTP/1.1 401 UNAUTHORIZED\r\nWWW-Authenticate: Bearer\r\n\r\n";
+const FORBIDDEN: &str = "HTTP/1.1 403 FORBIDDEN\r\n\r\n";
+const NOT_FOUND: &str = "HTTP/1.1 404 NOT FOUND\r\n\r\n";
+const METHOD_NOT_ALLOWED: &str = "HTTP/1.1 405 METHOD NOT ALLOWED\r\n\r\n";
+const CONFLICT: &str = "HTTP/1.1 409 CONFLICT\r\n\r\n";
+const REQUEST_HEADER_FIELDS_TOO_LARGE: &str = "HTTP/1.1 431 REQUEST HEADER FIELDS TOO LARGE\r\n\r\n";
+const PAYLOAD_TOO_LARGE: &str = "HTTP/1.1 413 PAYLOAD TOO LARGE\r\n\r\n";
+const SERVICE_UNAVAILABLE: &str = "HTTP/1.1 503 SERVICE UNAVAILABLE\r\n\r\n";
+const GATEWAY_TIMEOUT: &str = "HTTP/1.1 504 GATEWAY TIMEOUT\r\n\r\n";
+const UNSUPPORTED_MEDIA_TYPE: &str = "HTTP/1.1 415 UNSUPPORTED MEDIA TYPE\r\n\r\n";
+const UNPROCESSABLE_ENTITY: &str = "HTTP/1.1 422 UNPROCESSABLE ENTITY\r\n\r\n";
+const INSUFFICIENT_STORAGE: &str = "HTTP/1.1 507 INSUFFICIENT STORAGE\r\n\r\n";
+const INTERNAL_SERVER_ERROR: &str = "HTTP/1.1 500 INTERNAL SERVER ERROR\r\n\r\n";
+const CORS_ALLOW_METHODS: &str = "GET, POST, PUT, PATCH, DELETE, OPTIONS";
+const CORS_ALLOW_HEADERS: &str = "Content-Type, Authorization, X-Request-Id, Idempotency-Key";
+const LIST_DEFAULT_LIMIT: i64 = 20;
+const LIST_MAX_LIMIT: i64 = 100;
+const SAMPLE_MAX_SIZE: i64 = 100;
+const RSS_FEED_SIZE: i64 = 50;
+const RATE_LIMIT_MAX_CLIENTS: usize = 10_000;
+const RATE_LIMIT_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+const IDEMPOTENCY_MAX_KEYS: usize = 10_000;
+const IDEMPOTENCY_KEY_MAX_LEN: usize = 255;
+const LIST_SORT_COLUMNS: &[&str] = &["id", "title", "source", "created_at"];
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/58.0.3029.110 Safari/537.3";
+
+// Main function
+fn main() {
+    // Log at info level unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    // Load config and set database
+    let config = Config::from_env();
+    // With SKIP_MIGRATIONS the schema is managed elsewhere, so only check that it is there
+    let schema = if config.skip_migrations { verify_schema(&config) } else { set_database(&config).map_err(|e| e.to_string()) };
+    if let Err(e) = schema {
+        log::error!("{}", e);
+        return;
+    }
+
+    // Open the connection pool once; handlers check connections out of it per request. Connections are
+    // replaced after DB_MAX_LIFETIME_SECS, so after a failover none keep pointing at the old primary for long
+    let db = match Pool::builder()
+        .max_size(config.db_pool_size)
+        .max_lifetime(config.db_max_lifetime_secs.map(Duration::from_secs))
+        .build(PostgresConnectionManager::new(config.db.clone(), NoTls)) {
+        Ok(pool) => DbPool::new(pool),
         Err(e) => {
-            println!("Error fetching URL: {}", e);
-            return (INTERNAL_SERVER_ERROR.to_string(), format!("Error fetching URL: {}", e));
+            log::error!("{}", e);
+            return;
+        }
+    };
+    let state = Arc::new(AppState::new(config, db));
+
+    // Run a one-off scrape and exit when started with --scrape <source>
+    let args: Vec<String> = env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--scrape") {
+        process::exit(run_one_off_scrape(&state, args.get(index + 1).map(String::as_str)));
+    }
+
+    // Serve HTTPS directly when TLS_CERT and TLS_KEY are both set, plain HTTP otherwise
+    let tls = match (&state.config.tls_cert, &state.config.tls_key) {
+        (Some(cert), Some(key)) => match load_tls_config(cert, key) {
+            Ok(tls) => Some(tls),
+            Err(e) => {
+                log::error!("{}", e);
+                return;
+            }
         },
+        (None, None) => None,
+        _ => {
+            log::error!("TLS_CERT and TLS_KEY must be set together");
+            return;
+        }
     };
 
-    let document = Html::parse_document(&response);
-    let title_selector = match Selector::parse("h3.lister-item-header>a") {
-        Ok(sel) => sel,
+    // Start server and print the address it is listening on
+    let address = format!("{}:{}", state.config.bind_addr.trim(), state.config.port);
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
         Err(e) => {
-            println!("Error creating selector: {}", e);
-            return (INTERNAL_SERVER_ERROR.to_string(), "Error creating selector".to_string());
-        },
+            log::error!("could not listen on {}: {}", address, e);
+            return;
+        }
     };
+    match listener.local_addr() {
+        Ok(local) => log::info!("Server started at {}", local),
+        Err(_) => log::info!("Server started at {}", address),
+    }
+    log::info!("TLS {}", if tls.is_some() { "enabled" } else { "disabled; serving plain HTTP" });
 
-    let titles = document.select(&title_selector).map(|x| x.inner_html());
+    // Stop accepting on Ctrl-C or a container stop
+    let signal_state = Arc::clone(&state);
+    if let Err(e) = ctrlc::set_handler(move || {
+        log::info!("Shutting down...");
+        signal_state.shutting_down.store(true, Ordering::SeqCst);
+    }) {
+        log::warn!("could not install the shutdown signal handler: {}", e);
+    }
 
-    match Client::connect(db_url, NoTls) {
-        Ok(mut client) => {
-            for (item, number) in titles.zip(1..11) {
-                let article = Article {
-                    id: None,
-                    title: item.clone(),
-                    content: format!("{}. {}", number, item),
-                    source: "imdb".to_string(),
-                };
+    // Accept without blocking so the loop can notice the shutdown flag between connections
+    if let Err(e) = listener.set_nonblocking(true) {
+        log::error!("{}", e);
+        return;
+    }
 
-                if let Err(e) = client.execute(
-                    "INSERT INTO articles (title, content, source) VALUES ($1, $2, $3)",
-                    &[&article.title, &article.content, &article.source]
-                ) {
-                    println!("Error inserting article into database: {}", e);
-                    return (INTERNAL_SERVER_ERROR.to_string(), "Error inserting article into database".to_string());
+    // Handle each client on its own thread so a slow request doesn't block the others
+    while !state.shutting_down.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                if let Err(e) = stream.set_nonblocking(false) {
+                    log::error!("{}", e);
+                    continue;
+                }
+                // Bounds how long a keep-alive connection may sit idle waiting for its next request, and
+                // without keep-alive how long any read may stall, so a silent client can't hold a thread
+                let read_timeout = if state.config.keep_alive {
+                    state.config.keep_alive_timeout_secs
+                } else {
+                    state.config.read_timeout_secs
+                };
+                if let Err(e) = stream.set_read_timeout(Some(Duration::from_secs(read_timeout))) {
+                    log::error!("{}", e);
+                    continue;
                 }
+                let state = Arc::clone(&state);
+                let tls = tls.clone();
+                state.active_connections.fetch_add(1, Ordering::SeqCst);
+                state.total_connections.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || {
+                    match tls {
+                        Some(tls) => handle_tls_client(stream, peer.ip(), tls, &state),
+                        None => handle_client(stream, peer.ip(), &state),
+                    }
+                    state.active_connections.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+            Err(e) => {
+                log::error!("{}", e);
             }
+        }
+    }
+
+    // Let in-progress requests finish before the pool is dropped, for up to SHUTDOWN_TIMEOUT_SECS
+    let drain_deadline = Instant::now() + Duration::from_secs(state.config.shutdown_timeout_secs);
+    while state.active_connections.load(Ordering::SeqCst) > 0 {
+        if Instant::now() >= drain_deadline {
+            log::warn!(
+                "{} connections still open after {} seconds; exiting anyway",
+                state.active_connections.load(Ordering::SeqCst),
+                state.config.shutdown_timeout_secs
+            );
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// Run a single scrape without starting the server, returning the process exit code
+fn run_one_off_scrape(state: &AppState, source: Option<&str>) -> i32 {
+    let result = match source.map(|source| (source, find_scraper(source))) {
+        Some((_, Some(scraper))) => run_scrape(scraper, &state.config, &state.db, &ScrapeOptions::default(), None),
+        Some((other, None)) => {
+            log::error!("Unknown scrape source: {}", other);
+            return 2;
+        }
+        None => {
+            log::error!("Usage: newsapi --scrape <source>");
+            return 2;
+        }
+    };
 
-            println!("Scraping completed successfully");
-            (OK_RESPONSE.to_string(), "Scraping completed".to_string())
+    match result {
+        Ok(summary) => {
+            log::info!(
+                "Scrape finished: {} fetched, {} inserted, {} skipped in {} ms",
+                summary.fetched, summary.inserted, summary.skipped, summary.duration_ms
+            );
+            0
+        }
+        Err((_, message)) => {
+            log::error!("Scrape failed: {}", message);
+            1
         }
+    }
+}
+
+// Build the TLS server config from the PEM certificate chain at TLS_CERT and the private key at TLS_KEY
+fn load_tls_config(cert_path: &str, key_path: &str) -> Result<Arc<rustls::ServerConfig>, String> {
+    let certs = fs::File::open(cert_path).map_err(|e| format!("could not open TLS_CERT {}: {}", cert_path, e))?;
+    let certs = rustls_pemfile::certs(&mut io::BufReader::new(certs))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("could not read TLS_CERT {}: {}", cert_path, e))?;
+    let key = fs::File::open(key_path).map_err(|e| format!("could not open TLS_KEY {}: {}", key_path, e))?;
+    let key = rustls_pemfile::private_key(&mut io::BufReader::new(key))
+        .map_err(|e| format!("could not read TLS_KEY {}: {}", key_path, e))?
+        .ok_or_else(|| format!("no private key found in TLS_KEY {}", key_path))?;
+
+    rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map(Arc::new)
+        .map_err(|e| format!("invalid TLS certificate or key: {}", e))
+}
+
+// Handle a client over TLS; the handshake runs on the first read of the request
+fn handle_tls_client(stream: TcpStream, peer: IpAddr, tls: Arc<rustls::ServerConfig>, state: &Arc<AppState>) {
+    let connection = match rustls::ServerConnection::new(tls) {
+        Ok(connection) => connection,
         Err(e) => {
-            println!("Database connection error: {}", e);
-            (INTERNAL_SERVER_ERROR.to_string(), "Database connection error".to_string())
+            log::error!("Error starting TLS session: {}", e);
+            return;
         }
+    };
+
+    let mut stream = rustls::StreamOwned::new(connection, stream);
+    handle_client(&mut stream, peer, state);
+    // close_notify tells the client the response ended there rather than being cut off; a client that
+    // never finished the handshake (e.g. plain HTTP on the TLS port) has nothing to close
+    if stream.conn.is_handshaking() {
+        return;
+    }
+    stream.conn.send_close_notify();
+    if let Err(e) = stream.flush() {
+        log::warn!("Error closing TLS session: {}", e);
     }
 }
 
-// Handle delete by source function
-fn handle_delete_by_source(db_url: &str, source: &str) -> (String, String) {
-    match Client::connect(db_url, NoTls) {
-        Ok(mut client) => {
-            let rows_affected = client.execute("DELETE FROM articles WHERE source = $1", &[&source]).unwrap();
+// Handle client function; generic over the stream so plain TCP and TLS connections share it.
+// With KEEP_ALIVE, requests are served off the same connection until the client closes it, sends
+// Connection: close, stays idle for KEEP_ALIVE_TIMEOUT_SECS or has sent KEEP_ALIVE_MAX_REQUESTS
+fn handle_client<S: Read + Write>(mut stream: S, peer: IpAddr, state: &Arc<AppState>) {
+    let config = &state.config;
+    // Bytes read past the end of one request belong to the next one
+    let mut pending = Vec::new();
+    let mut served = 0;
 
-            if rows_affected == 0 {
-                return (NOT_FOUND.to_string(), "No articles found for the given source".to_string());
+    while served == 0 || !state.shutting_down.load(Ordering::SeqCst) {
+        let request = match read_request(&mut stream, &mut pending, config) {
+            // The client closed the connection between requests
+            Ok(Ok(request)) if request.is_empty() => return,
+            Ok(Ok(request)) => request,
+            Ok(Err((status_line, content))) => {
+                // The oversized request wasn't read to its end, so the connection can't be reused
+                let status_line = with_header(&status_line, "Connection", "close");
+                return write_response(&mut stream, &with_cors_headers(&status_line, config), content.as_bytes());
+            }
+            // An idle keep-alive connection timing out is how it normally ends
+            Err(e) if served > 0 && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                log::warn!("Timed out reading a request from {}", peer);
+                return;
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                return;
             }
+        };
+        served += 1;
 
-            (OK_RESPONSE.to_string(), "Articles deleted".to_string())
+        if !handle_request(&mut stream, &request, peer, state, served) {
+            return;
         }
-        Err(_) => (INTERNAL_SERVER_ERROR.to_string(), "Database connection error".to_string()),
     }
 }
 
-// Set database function
-fn set_database(db_url: &str) -> Result<(), PostgresError> {
-    // Connect to database
-    let mut client = Client::connect(db_url, NoTls)?;
+// Handle request function: answers the served-th request read off the connection; returns whether the
+// connection can be kept open for another
+fn handle_request<S: Read + Write>(stream: &mut S, request: &str, peer: IpAddr, state: &Arc<AppState>, served: usize) -> bool {
+    let config = &state.config;
+    let pool = &state.db;
 
-    // Create table
-    client.batch_execute(
-        "CREATE TABLE IF NOT EXISTS articles (
-            id SERIAL PRIMARY KEY,
-            title VARCHAR NOT NULL,
-            content TEXT NOT NULL,
-            source VARCHAR NOT NULL
-        )"
-    )?;
-    Ok(())
+    let started = Instant::now();
+    let mut timing = ServerTiming::default();
+    db::reset_query_count();
+    let r = match HttpRequest::parse(request) {
+        Ok(r) => r,
+        Err(e) => {
+            let (status_line, content) = error_response(BAD_REQUEST, &e.to_string());
+            let status_line = with_header(&status_line, "Connection", "close");
+            write_response(stream, &with_cors_headers(&status_line, config), content.as_bytes());
+            return false;
+        }
+    };
+    let r = &r;
+    let keep_alive = wants_keep_alive(r, config, served);
+    let parse_time = started.elapsed();
+    let rate_limited = take_rate_limit_token(state, peer).err();
+    let (status_line, content) = match (r.method, r.segments.as_slice()) {
+        _ if rate_limited.is_some() => {
+            let retry_after = rate_limited.unwrap_or_default();
+            error_response(
+                &format!("HTTP/1.1 429 TOO MANY REQUESTS\r\nRetry-After: {}\r\n\r\n", retry_after),
+                &format!("Rate limit exceeded; retry in {} seconds", retry_after),
+            )
+        }
+        _ if has_ambiguous_framing(r) => {
+            error_response(BAD_REQUEST, "Conflicting Content-Length or Transfer-Encoding headers")
+        }
+        _ if has_too_many_query_params(r, config) => {
+            error_response(BAD_REQUEST, &format!("At most {} query parameters are allowed", config.max_query_params))
+        }
+        ("OPTIONS", ["*"]) => (SERVER_OPTIONS_RESPONSE.to_string(), String::new()),
+        // CORS preflight; the Access-Control-* headers are added to every response below
+        ("OPTIONS", _) => (NO_CONTENT.to_string(), String::new()),
+        // With API_KEY set, every write needs the key; reads stay public
+        _ if is_write_request(r) && config.api_key.as_deref().is_some_and(|key| !is_authorized(r, key)) => {
+            error_response(UNAUTHORIZED, "Missing or invalid API key")
+        }
+        // Maintenance mode: reads keep working, writes (scrapes included) are refused until switched off
+        _ if is_write_request(r) && state.read_only.load(Ordering::SeqCst) && r.segments != ["admin", "read-only"] => {
+            error_response(SERVICE_UNAVAILABLE, "Server is in read-only mode")
+        }
+        _ if config.canonical_redirects && get_canonical_location(r).is_some() => {
+            let location = get_canonical_location(r).unwrap_or_default();
+            (format!("HTTP/1.1 308 PERMANENT REDIRECT\r\nLocation: {}\r\n\r\n", location), String::new())
+        }
+        ("GET", ["favicon.ico"]) => (NO_CONTENT.to_string(), String::new()),
+        ("GET", ["ping"]) => (OK_TEXT_RESPONSE.to_string(), "pong".to_string()),
+        ("GET", ["version"]) => handle_version_request(config),
+        ("POST", ["articles", _, "move", _]) => handle_move_request(r, pool),
+        ("POST", ["articles", _, "promote"]) => handle_reorder_request(r, pool, true),
+        ("POST", ["articles", _, "demote"]) => handle_reorder_request(r, pool, false),
+        ("POST", ["articles", "import-from-url"]) => handle_import_request(r, config, pool),
+        ("POST", ["articles", "batch-get"]) => handle_batch_get_request(r, config, pool),
+        ("POST", ["articles", "bulk"]) => handle_bulk_post_request(r, config, pool),
+        ("POST", ["articles"]) => with_idempotency(r, state, || handle_post_request(r, config, pool)),
+        ("GET", ["articles", _, "exists"]) => handle_exists_request(r, pool),
+        ("GET", ["articles", _, "image"]) => handle_image_request(r, config, pool),
+        ("GET", ["articles", "schema.json"]) => handle_schema_request(config),
+        ("GET", ["articles.geojson"]) => handle_geojson_request(config, pool),
+        ("GET", ["articles.rss"]) => handle_rss_request(r, config, pool, None),
+        ("GET", ["feed", file]) => match file.strip_suffix(".xml") {
+            Some(source) if config.source_feeds => handle_rss_request(r, config, pool, Some(source)),
+            _ => error_response(NOT_FOUND, "404 Not Found"),
+        },
+        ("GET", ["articles", "sample"]) => handle_sample_request(r, config, pool),
+        ("GET", ["articles", "since", _]) => handle_since_request(r, config, pool),
+        ("GET", ["articles", "search"]) => handle_search_request(r, config, pool),
+        // The stream holds the connection open, so it writes its own response
+        ("GET", ["articles", "stream"]) if config.article_stream => {
+            handle_article_stream(stream, state);
+            return false;
+        }
+        ("GET", ["articles", "stream"]) => error_response(NOT_FOUND, "Article stream is disabled"),
+        ("GET", ["articles", _]) => handle_get_request(r, config, pool, &mut timing),
+        // STREAM_LIST writes plain list pages straight to the socket; nothing is left to send afterwards
+        ("GET", ["articles"]) => {
+            let out = if config.stream_list { Some(stream as &mut dyn Write) } else { None };
+            match handle_get_all_request(r, config, pool, &mut timing, out) {
+                Some(response) => response,
+                // A streamed body ends when the connection does
+                None => {
+                    if let Some(warning) = query_budget_warning(config, r, db::query_count()) {
+                        log::warn!("{}", warning);
+                    }
+                    log_request(config, r, "200", parse_time, started.elapsed() - parse_time, started);
+                    return false;
+                }
+            }
+        }
+        ("PUT", ["articles", _]) => handle_put_request(r, config, pool),
+        ("PATCH", ["articles", _]) => handle_patch_request(r, config, pool),
+        ("DELETE", ["articles"]) => handle_delete_all_request(r, pool),
+        ("DELETE", ["articles", "source", source]) => handle_delete_by_source(config, pool, source),
+        ("DELETE", ["articles", _]) => handle_delete_request(r, pool),
+        ("GET", ["oembed"]) => handle_oembed_request(r, config, pool),
+        ("POST", ["scrape", "test"]) => handle_scrape_test(r, config),
+        ("POST", ["scrape", "all"]) => handle_scrape_all(r, state),
+        ("POST", ["scrape", _]) => handle_scrape(r, state, false),
+        // Replace the source's articles with a fresh scrape in one transaction
+        ("POST", ["scrape", _, "refresh"]) => handle_scrape(r, state, true),
+        // Legacy spelling of DELETE /articles/source/:source, kept for registered scrape sources
+        ("DELETE", ["scrape", "source", source]) if find_scraper(source).is_some() => {
+            mark_deprecated(handle_delete_by_source(config, pool, source), config, r, &format!("/articles/source/{}", source))
+        }
+        ("GET", ["admin", "status"]) => handle_admin_status(r, state),
+        ("POST", ["admin", "export-backup"]) => handle_export_backup(r, state),
+        ("GET", ["exports", _]) => handle_export_download(r, state),
+        ("POST", ["admin", "purge-by-query"]) => handle_purge_by_query(r, config, pool),
+        ("POST", ["admin", "read-only"]) => handle_read_only_request(r, state),
+        ("POST", ["debug", "echo"]) => handle_debug_echo(r, config),
+        ("GET", ["admin", "category-rules"]) => handle_list_category_rules(r, config, pool),
+        ("POST", ["admin", "category-rules"]) => handle_create_category_rule(r, config, pool),
+        ("POST", ["admin", "category-rules", "apply"]) => handle_apply_category_rules(r, config, pool),
+        ("DELETE", ["admin", "category-rules", _]) => handle_delete_category_rule(r, config, pool),
+        // The path exists but not for this method: 405 with the methods it does take
+        (_, segments) => match get_allowed_methods(segments) {
+            Some(allow) => error_response(&with_header(METHOD_NOT_ALLOWED, "Allow", allow), "Method not allowed"),
+            None => error_response(NOT_FOUND, "404 Not Found"),
+        },
+    };
+    let handler_time = started.elapsed() - parse_time;
+
+    let (status_line, content) = if config.error_negotiation && prefers_plain_text(r) {
+        to_plain_text_error(status_line, content)
+    } else {
+        (status_line, content)
+    };
+
+    // Successful GETs carry an ETag of their body; a client that already has it gets a bodiless 304
+    let (status_line, content) = if config.etags && r.method == "GET" && status_line.starts_with("HTTP/1.1 200 ") {
+        let etag = get_etag(&content);
+        if etag_matches(r, &etag) {
+            (with_header(NOT_MODIFIED, "ETag", &etag), String::new())
+        } else {
+            (with_header(&status_line, "ETag", &etag), content)
+        }
+    } else {
+        (status_line, content)
+    };
+
+    let status_line = if config.server_timing || r.query.get_flag("timing").unwrap_or(false) {
+        // Timing-Allow-Origin lets a frontend on CORS_ALLOW_ORIGIN read the metrics
+        let status_line = with_header(&status_line, "Server-Timing", &timing.header_value(started.elapsed()));
+        with_header(&status_line, "Timing-Allow-Origin", &config.cors_allow_origin)
+    } else {
+        status_line
+    };
+
+    // Bodies of at least GZIP_MIN_BYTES are gzipped for clients that accept it
+    let (status_line, body) = if config.gzip_responses && content.len() >= config.gzip_min_bytes && accepts_gzip(r) {
+        match gzip(content.as_bytes()) {
+            Ok(compressed) => {
+                let status_line = with_header(&status_line, "Content-Encoding", "gzip");
+                (with_header(&status_line, "Vary", "Accept-Encoding"), compressed)
+            }
+            Err(e) => {
+                log::error!("Error compressing response: {}", e);
+                (status_line, content.into_bytes())
+            }
+        }
+    } else {
+        (status_line, content.into_bytes())
+    };
+
+    let status_line = with_header(&status_line, "Connection", if keep_alive { "keep-alive" } else { "close" });
+    write_response(stream, &with_cors_headers(&status_line, config), &body);
+
+    if let Some(warning) = query_budget_warning(config, r, db::query_count()) {
+        log::warn!("{}", warning);
+    }
+    log_request(config, r, status_line.split_whitespace().nth(1).unwrap_or("-"), parse_time, handler_time, started);
+    keep_alive
 }
 
-// Get ID function
-fn get_id(request: &str) -> &str {
-    request.split("/").nth(2).unwrap_or_default().split_whitespace().next().unwrap_or_default()
+// With QUERY_BUDGET, the warning for a request that ran more queries than that (often an N+1 pattern)
+fn query_budget_warning(config: &Config, r: &HttpRequest, queries: usize) -> Option<String> {
+    let budget = config.query_budget?;
+    (queries > budget).then(|| format!("{} {} ran {} queries, over the QUERY_BUDGET of {}", r.method, r.path, queries, budget))
 }
 
-// Deserialize article from request body
-fn get_article_request_body(request: &str) -> Result<Article, serde_json::Error> {
-    serde_json::from_str(request.split("\r\n\r\n").last().unwrap_or_default())
+// Whether the connection can take another request after this one, the served-th on it: HTTP/1.1 stays
+// open unless the client sends Connection: close, HTTP/1.0 only with Connection: keep-alive. Requests
+// whose body framing can't be trusted (ambiguous lengths or chunked bodies, which aren't decoded) always
+// close, and so does the KEEP_ALIVE_MAX_REQUESTS-th, so one client can't hold a thread indefinitely
+fn wants_keep_alive(request: &HttpRequest, config: &Config, served: usize) -> bool {
+    if !config.keep_alive || served >= config.keep_alive_max_requests {
+        return false;
+    }
+    if has_ambiguous_framing(request) || request.header("Transfer-Encoding").is_some() {
+        return false;
+    }
+    let has_token = |token: &str| {
+        request.header_values("Connection").flat_map(|value| value.split(',')).any(|item| item.trim().eq_ignore_ascii_case(token))
+    };
+    match request.version {
+        "HTTP/1.0" => has_token("keep-alive"),
+        _ => !has_token("close"),
+    }
+}
+
+// One line per request: method, path, status code and handling time; LOG_TIMINGS switches to key=value
+// fields with the parse and handler phases split out, for log aggregators
+fn log_request(config: &Config, r: &HttpRequest, status: &str, parse_time: Duration, handler_time: Duration, started: Instant) {
+    let total_time = started.elapsed();
+    if config.log_timings {
+        log::info!(
+            "method={} path={} status={} parse_ms={:.3} handler_ms={:.3} total_ms={:.3}",
+            r.method,
+            r.path,
+            status,
+            parse_time.as_secs_f64() * 1000.0,
+            handler_time.as_secs_f64() * 1000.0,
+            total_time.as_secs_f64() * 1000.0
+        );
+    } else {
+        log::info!("{} {} {} {:.1}ms", r.method, r.path, status, total_time.as_secs_f64() * 1000.0);
+    }
+}
+
+// Read one request off the socket: the head up to the blank line, then Content-Length bytes of body.
+// Oversized heads and bodies are answered with 431 and 413 instead of being read in full.
+// Anything read past the body is left in pending for the next call; an empty request means the
+// client closed the connection before sending anything
+fn read_request(stream: &mut impl Read, pending: &mut Vec<u8>, config: &Config) -> io::Result<Result<String, (String, String)>> {
+    let mut data = std::mem::take(pending);
+    let mut buffer = [0; 4096];
+
+    let head_end = loop {
+        if let Some(index) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break index + 4;
+        }
+        if data.len() > config.max_header_bytes {
+            return Ok(Err(error_response(REQUEST_HEADER_FIELDS_TOO_LARGE, &format!("Request headers exceed {} bytes", config.max_header_bytes))));
+        }
+
+        let size = stream.read(&mut buffer)?;
+        if size == 0 {
+            return Ok(Ok(String::from_utf8_lossy(&data).into_owned()));
+        }
+        data.extend_from_slice(&buffer[..size]);
+    };
+
+    let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+    let content_length = HttpRequest::parse(&head)
+        .ok()
+        .and_then(|head| head.header("Content-Length").and_then(|v| v.parse::<usize>().ok()))
+        .unwrap_or(0);
+    if content_length > config.max_body_bytes {
+        return Ok(Err(error_response(PAYLOAD_TOO_LARGE, &format!("Request body exceeds {} bytes", config.max_body_bytes))));
+    }
+
+    while data.len() < head_end + content_length {
+        let size = stream.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+        data.extend_from_slice(&buffer[..size]);
+    }
+    if data.len() > head_end + content_length {
+        *pending = data.split_off(head_end + content_length);
+    }
+
+    Ok(Ok(String::from_utf8_lossy(&data).into_owned()))
+}
+
+// Write the response, logging clients that hung up before it was sent. Content-Length tells a
+// keep-alive client where the body ends; 204 and 304 responses never have one
+fn write_response(stream: &mut impl Write, status_line: &str, body: &[u8]) {
+    let status_line = match status_line.split_whitespace().nth(1) {
+        Some("204" | "304") => status_line.to_string(),
+        _ => with_header(status_line, "Content-Length", &body.len().to_string()),
+    };
+    if let Err(e) = stream.write_all(status_line.as_bytes()).and_then(|_| stream.write_all(body)) {
+        match e.kind() {
+            ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => {
+                log::warn!("Client disconnected before the response was sent: {}", e);
+            }
+            _ => log::error!("Error writing response: {}", e),
+        }
+    }
+}
+
+// Whether Accept-Encoding lists gzip, or * without gzip, with a q-value above 0; an explicit gzip;q=0 wins over *
+fn accepts_gzip(request: &HttpRequest) -> bool {
+    let mut any = None;
+    for (coding, q) in request.header("Accept-Encoding").unwrap_or_default().split(',').map(parse_accept_item) {
+        if coding.eq_ignore_ascii_case("gzip") {
+            return q > 0.0;
+        }
+        if coding == "*" {
+            any = Some(q > 0.0);
+        }
+    }
+    any.unwrap_or(false)
+}
+
+// Split an Accept-style list item like "gzip;q=0.5" into its value and q-value (1 when missing or unparseable)
+fn parse_accept_item(item: &str) -> (&str, f32) {
+    let mut parts = item.split(';').map(str::trim);
+    let value = parts.next().unwrap_or_default();
+    let q = parts
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+        .and_then(|(_, q)| q.trim().parse().ok())
+        .unwrap_or(1.0);
+    (value, q)
+}
+
+// Weak ETag from a hash of the response body; weak because the gzipped and plain bodies share it.
+// DefaultHasher has fixed keys, so the tag is stable across restarts of the same build
+fn get_etag(body: &str) -> String {
+    format!("W/\"{:016x}\"", hash_str(body))
+}
+
+fn hash_str(value: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Whether If-None-Match lists the ETag (compared weakly, so a W/ prefix on either side is ignored) or is *
+fn etag_matches(request: &HttpRequest, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    request
+        .header("If-None-Match")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+// Gzip a response body
+fn gzip(body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+// Controllers
+
+// Handle POST request function
+fn handle_post_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    match (get_valid_article(request, config), pool.get()) {
+        (Ok(mut article), Ok(mut client)) => {
+            if config.normalize_source {
+                article.source = normalize_source(&article.source);
+            }
+
+            // The same transaction as bulk inserts, so a failed insert evicts nothing and concurrent POSTs
+            // can't both squeeze under MAX_ARTICLES
+            match insert_articles(&mut client, config, std::slice::from_mut(&mut article)) {
+                Ok(true) => {}
+                Ok(false) => return error_response(INSUFFICIENT_STORAGE, "Article limit reached"),
+                Err(e) => {
+                    log::error!("Error inserting article: {}", e);
+                    return db_error_response(&e, "Error");
+                }
+            }
+            announce_article(config, &article, request.header("X-Request-Id"));
+
+            let location = format!("/articles/{}", article.id.unwrap_or_default());
+            (with_header(CREATED_RESPONSE, "Location", &location), serde_json::to_string(&article).unwrap())
+        }
+        (Err(message), _) => error_response(BAD_REQUEST, &message),
+        (_, Err(e)) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle bulk POST request function: inserts a JSON array of articles in one transaction, all or nothing,
+// or with ?mode=partial each article on its own
+fn handle_bulk_post_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    match request.query.get_str("mode") {
+        None | Some("atomic") => {}
+        Some("partial") if config.bulk_partial_mode => return handle_partial_bulk_post(request, config, pool),
+        Some(other) => return error_response(BAD_REQUEST, &format!("Unsupported mode '{}'; use atomic or partial", other)),
+    }
+
+    let mut articles = match get_articles_request_body(request) {
+        Ok(articles) => articles,
+        Err(e) => return error_response(BAD_REQUEST, &format!("Invalid article list: {}", e)),
+    };
+    for (index, article) in articles.iter_mut().enumerate() {
+        if let Err(message) = validate_article(article, config) {
+            return error_response(BAD_REQUEST, &format!("Article {}: {}", index, message));
+        }
+        if config.normalize_source {
+            article.source = normalize_source(&article.source);
+        }
+    }
+
+    let mut client = match pool.get() {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            return error_response(INTERNAL_SERVER_ERROR, "Database connection error");
+        }
+    };
+
+    match insert_articles(&mut client, config, &mut articles) {
+        Ok(true) => {
+            announce_articles(config, &articles, request.header("X-Request-Id"));
+            (OK_RESPONSE.to_string(), serde_json::json!({ "inserted": articles.len() }).to_string())
+        }
+        Ok(false) => error_response(INSUFFICIENT_STORAGE, "Article limit reached"),
+        Err(e) => {
+            log::error!("Error inserting articles: {}", e);
+            db_error_response(&e, "Error inserting articles")
+        }
+    }
+}
+
+// Insert each article of a bulk POST independently and answer with one { index, status, id?, error? }
+// result per item, so clients can retry only the failures
+fn handle_partial_bulk_post(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    let body = request.body;
+    let items = match serde_json::from_str::<Vec<serde_json::Value>>(body) {
+        Ok(items) => items,
+        Err(e) => return error_response(BAD_REQUEST, &format!("Invalid article list: {}", e)),
+    };
+
+    let mut client = match pool.get() {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            return error_response(INTERNAL_SERVER_ERROR, "Database connection error");
+        }
+    };
+
+    let mut created = Vec::new();
+    let results: Vec<serde_json::Value> = items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| match insert_bulk_item(config, &mut client, item) {
+            Ok(article) => {
+                let result = match article.id {
+                    Some(id) => serde_json::json!({ "index": index, "status": 201, "id": id }),
+                    None => serde_json::json!({ "index": index, "status": 201 }),
+                };
+                created.push(article);
+                result
+            }
+            Err((status_line, content)) => {
+                let status = status_line.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok());
+                let error = serde_json::from_str::<serde_json::Value>(&content).ok().and_then(|body| body.get("error").cloned());
+                serde_json::json!({ "index": index, "status": status, "error": error })
+            }
+        })
+        .collect();
+    announce_articles(config, &created, request.header("X-Request-Id"));
+
+    (OK_RESPONSE.to_string(), serde_json::to_string(&results).unwrap())
+}
+
+// Validate and insert one item of a partial bulk POST, giving the stored article or the error response it failed with
+fn insert_bulk_item(config: &Config, client: &mut Connection, item: serde_json::Value) -> Result<Article, (String, String)> {
+    let mut article = serde_json::from_value::<Article>(item)
+        .map_err(|e| error_response(BAD_REQUEST, &format!("Invalid article: {}", e)))?;
+    validate_article(&article, config).map_err(|message| error_response(BAD_REQUEST, &message))?;
+    if config.normalize_source {
+        article.source = normalize_source(&article.source);
+    }
+
+    match insert_articles(client, config, std::slice::from_mut(&mut article)) {
+        Ok(true) => Ok(article),
+        Ok(false) => Err(error_response(INSUFFICIENT_STORAGE, "Article limit reached")),
+        Err(e) => {
+            log::error!("Error inserting article: {}", e);
+            Err(db_error_response(&e, "Error inserting article"))
+        }
+    }
+}
+
+// Insert the articles in a single transaction, filling in their generated fields (except for
+// batches of BULK_COPY_THRESHOLD or more, which use COPY); returns false without inserting
+// anything when they don't fit under MAX_ARTICLES
+fn insert_articles(client: &mut Connection, config: &Config, articles: &mut [Article]) -> Result<bool, PostgresError> {
+    let mut transaction = client.transaction()?;
+    let newest_before = lock_for_article_limit(&mut transaction, config)?;
+    apply_category_rules(&mut transaction, config, articles)?;
+
+    // Large batches stream through COPY, which is much faster than row-by-row inserts; a single
+    // article always takes the INSERT path so its generated fields come back
+    if articles.len() > 1 && articles.len() >= config.bulk_copy_threshold {
+        let writer = transaction.copy_in(
+            "COPY articles (title, content, source, image_url, lat, lon, url, normalized_url, category) FROM STDIN (FORMAT binary)"
+        )?;
+        let mut writer = BinaryCopyInWriter::new(
+            writer,
+            &[Type::VARCHAR, Type::TEXT, Type::VARCHAR, Type::VARCHAR, Type::FLOAT8, Type::FLOAT8, Type::VARCHAR, Type::VARCHAR, Type::VARCHAR],
+        );
+        for article in articles.iter() {
+            writer.write(&[
+                &article.title,
+                &encrypt_content(config, &article.content),
+                &article.source,
+                &article.image_url,
+                &article.lat,
+                &article.lon,
+                &article.url,
+                &normalize_url(config, article.url.as_deref()),
+                &article.category,
+            ])?;
+        }
+        writer.finish()?;
+        if !trim_to_article_limit(&mut transaction, config, newest_before, articles.len() as i64)? {
+            return Ok(false);
+        }
+        transaction.commit()?;
+        return Ok(true);
+    }
+
+    for article in articles.iter_mut() {
+        let row = transaction.query_one(
+            "INSERT INTO articles (title, content, source, image_url, lat, lon, url, normalized_url, category)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id, position, created_at, updated_at",
+            &[
+                &article.title,
+                &encrypt_content(config, &article.content),
+                &article.source,
+                &article.image_url,
+                &article.lat,
+                &article.lon,
+                &article.url,
+                &normalize_url(config, article.url.as_deref()),
+                &article.category,
+            ]
+        )?;
+        article.id = row.get(0);
+        article.position = row.get(1);
+        article.created_at = row.get(2);
+        article.updated_at = row.get(3);
+    }
+
+    if !trim_to_article_limit(&mut transaction, config, newest_before, articles.len() as i64)? {
+        return Ok(false);
+    }
+    transaction.commit()?;
+    Ok(true)
+}
+
+// Handle GET request function
+fn handle_get_request(request: &HttpRequest, config: &Config, pool: &DbPool, timing: &mut ServerTiming) -> (String, String) {
+    let view = match ViewOptions::from_query(&request.query) {
+        Ok(view) => view,
+        Err(e) => return error_response(BAD_REQUEST, &e.to_string()),
+    };
+
+    match (request.segment(1).parse::<i32>(), pool.get()) {
+        (Ok(id), Ok(mut client)) =>
+            match timing.record("db", || client.query_opt("SELECT * FROM articles WHERE id = $1", &[&id])) {
+                Ok(Some(row)) => {
+                    let content = timing.record("serialize", || render_json(&view_article(&row, config, &view), &view));
+                    (OK_RESPONSE.to_string(), content)
+                }
+                Ok(None) if config.missing_as_null => (OK_RESPONSE.to_string(), "null".to_string()),
+                Ok(None) => error_response(NOT_FOUND, "Article not found"),
+                Err(e) => {
+                    log::error!("Error fetching article: {}", e);
+                    error_response(INTERNAL_SERVER_ERROR, "Error")
+                }
+            }
+
+        (Err(_), _) => error_response(BAD_REQUEST, "Invalid article id"),
+        (_, Err(e)) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle exists request function: always 200 with { "exists": bool }
+fn handle_exists_request(request: &HttpRequest, pool: &DbPool) -> (String, String) {
+    let id = match request.segment(1).parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return error_response(BAD_REQUEST, "Invalid article id"),
+    };
+
+    match pool.get() {
+        Ok(mut client) =>
+            match client.query_one("SELECT EXISTS(SELECT 1 FROM articles WHERE id = $1)", &[&id]) {
+                Ok(row) => {
+                    let exists: bool = row.get(0);
+                    (OK_RESPONSE.to_string(), serde_json::json!({ "exists": exists }).to_string())
+                }
+                Err(e) => {
+                    log::error!("Error checking article: {}", e);
+                    error_response(INTERNAL_SERVER_ERROR, "Error")
+                }
+            }
+
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle image request function: redirects to the article's stored image URL
+fn handle_image_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    if !config.article_images {
+        return error_response(NOT_FOUND, "404 Not Found");
+    }
+
+    match (request.segment(1).parse::<i32>(), pool.get()) {
+        (Ok(id), Ok(mut client)) =>
+            match client.query_opt("SELECT image_url FROM articles WHERE id = $1", &[&id]) {
+                Ok(Some(row)) => match row.get::<_, Option<String>>(0) {
+                    Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+                        (format!("HTTP/1.1 302 FOUND\r\nLocation: {}\r\n\r\n", url), String::new())
+                    }
+                    _ => error_response(NOT_FOUND, "Article has no image"),
+                },
+                Ok(None) => error_response(NOT_FOUND, "Article not found"),
+                Err(e) => {
+                    log::error!("Error fetching article image: {}", e);
+                    error_response(INTERNAL_SERVER_ERROR, "Error")
+                }
+            }
+
+        (Err(_), _) => error_response(BAD_REQUEST, "Invalid article id"),
+        (_, Err(e)) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle GET all request function
+fn handle_get_all_request(
+    request: &HttpRequest,
+    config: &Config,
+    pool: &DbPool,
+    timing: &mut ServerTiming,
+    out: Option<&mut dyn Write>,
+) -> Option<(String, String)> {
+    let options = match ListOptions::from_query(&request.query) {
+        Ok(options) => options,
+        Err(e) => return Some(error_response(BAD_REQUEST, &e.to_string())),
+    };
+
+    // Only source can be faceted for now
+    if let Some(facet) = options.facets.iter().find(|facet| facet.as_str() != "source") {
+        return Some(error_response(BAD_REQUEST, &format!("Unsupported facet '{}'; only source is supported", facet)));
+    }
+    let facet_sources = config.list_facets && !options.facets.is_empty();
+    if [options.min_content_len, options.max_content_len].iter().flatten().any(|&len| len < 0) {
+        return Some(error_response(BAD_REQUEST, "min_content_len and max_content_len must not be negative"));
+    }
+    if let (Some(min), Some(max)) = (options.min_content_len, options.max_content_len) {
+        if min > max {
+            return Some(error_response(BAD_REQUEST, "min_content_len must not be greater than max_content_len"));
+        }
+    }
+    // Encrypted or compressed content is stored at a different length than it reads back
+    let filters_content_len = options.min_content_len.is_some() || options.max_content_len.is_some();
+    if filters_content_len && (config.content_cipher.is_some() || config.compress_content) {
+        return Some(error_response(BAD_REQUEST, "Content length filters are unavailable when content is encrypted or compressed"));
+    }
+    if options.after.is_some() && !config.keyset_pagination {
+        return Some(error_response(BAD_REQUEST, "Keyset pagination is disabled"));
+    }
+    // One row past the page tells whether there is a next cursor
+    let keyset_limit = options.limit + 1;
+
+    // Stored sources are normalized, so the filter value must be too
+    let source = options.source.as_deref().map(|source| {
+        if config.normalize_source { normalize_source(source) } else { source.to_string() }
+    });
+
+    Some(match pool.get() {
+        Ok(mut client) => {
+            let mut conditions = Vec::new();
+            let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+            if !config.hidden_sources.is_empty() && !options.include_hidden {
+                params.push(&config.hidden_sources);
+                conditions.push(format!("source <> ALL(${})", params.len()));
+            }
+            if let Some(source) = &source {
+                params.push(source);
+                conditions.push(format!("source = ${}", params.len()));
+            }
+            if let Some(category) = &options.category {
+                params.push(category);
+                conditions.push(format!("category = ${}", params.len()));
+            }
+            if let Some(min) = &options.min_content_len {
+                params.push(min);
+                conditions.push(format!("length(content) >= ${}::bigint", params.len()));
+            }
+            if let Some(max) = &options.max_content_len {
+                params.push(max);
+                conditions.push(format!("length(content) <= ${}::bigint", params.len()));
+            }
+            let filter = if conditions.is_empty() { String::new() } else { format!(" WHERE {}", conditions.join(" AND ")) };
+            // ?ordered=true uses the manual order, then ?sort; otherwise newest first unless LIST_NEWEST_FIRST is off.
+            // id breaks ties so pages don't overlap when the sort column repeats
+            // ?after always pages in id order, since the cursor is an id
+            let order = match options.sort {
+                _ if options.after.is_some() => " ORDER BY id".to_string(),
+                _ if options.ordered => " ORDER BY position, id".to_string(),
+                Some((column, direction)) => format!(" ORDER BY {} {}, id {}", column, direction, direction),
+                None if config.list_newest_first => " ORDER BY created_at DESC, id DESC".to_string(),
+                None => " ORDER BY id".to_string(),
+            };
+            // distinct_titles keeps the lowest id for each title
+            let from = if options.distinct_titles {
+                format!("(SELECT DISTINCT ON (title) * FROM articles{} ORDER BY title, id) AS articles", filter)
+            } else {
+                format!("articles{}", filter)
+            };
+
+            // The total only feeds the offset Link header, so skip counting when links are off or paging by cursor
+            let total = if config.pagination_links && options.after.is_none() {
+                match timing.record("db", || client.query_one(&format!("SELECT COUNT(*) FROM {}", from), &params)) {
+                    Ok(row) => Some(row.get::<_, i64>(0)),
+                    Err(e) => {
+                        log::error!("Error counting articles: {}", e);
+                        return Some(error_response(INTERNAL_SERVER_ERROR, "Error counting articles"));
+                    }
+                }
+            } else {
+                None
+            };
+
+            // Facet counts cover the whole filtered set, not just the requested page
+            let facets = if facet_sources {
+                let query = format!("SELECT source, COUNT(*) FROM {} GROUP BY source", from);
+                match timing.record("db", || client.query(&query, &params)) {
+                    Ok(rows) => Some(rows.iter().map(|row| (row.get::<_, String>(0), row.get::<_, i64>(1))).collect::<BTreeMap<_, _>>()),
+                    Err(e) => {
+                        log::error!("Error counting articles by source: {}", e);
+                        return Some(error_response(INTERNAL_SERVER_ERROR, "Error counting articles by source"));
+                    }
+                }
+            } else {
+                None
+            };
+
+            // Keyset pages filter on id after the other conditions (and after distinct_titles picks its rows)
+            let page = match &options.after {
+                Some(after) => {
+                    let joiner = if options.distinct_titles || conditions.is_empty() { "WHERE" } else { "AND" };
+                    let page = format!(" {} id > ${}{} LIMIT ${}", joiner, params.len() + 1, order, params.len() + 2);
+                    params.push(after);
+                    params.push(&keyset_limit);
+                    page
+                }
+                None => {
+                    let page = format!("{} LIMIT ${} OFFSET ${}", order, params.len() + 1, params.len() + 2);
+                    params.push(&options.limit);
+                    params.push(&options.offset);
+                    page
+                }
+            };
+            let query = format!("SELECT * FROM {}{}", from, page);
+
+            // A plain array page can go out row by row; faceted and cursor pages need the envelope built first
+            if let (Some(out), None, None) = (out, &facets, options.after) {
+                let links = total.map(|total| get_pagination_links(request, options.limit, options.offset, total)).unwrap_or_default();
+                let status_line = if links.is_empty() { OK_RESPONSE.to_string() } else { with_header(OK_RESPONSE, "Link", &links) };
+                return match client.query_raw(&query, params.iter().copied()) {
+                    Ok(rows) => {
+                        let status_line = with_header(&status_line, "Connection", "close");
+                        stream_articles(out, &with_cors_headers(&status_line, config), rows, config, &options.view);
+                        None
+                    }
+                    Err(e) => {
+                        log::error!("Error fetching articles: {}", e);
+                        Some(error_response(INTERNAL_SERVER_ERROR, "Error fetching articles"))
+                    }
+                };
+            }
+
+            match timing.record("db", || client.query(&query, &params)) {
+                Ok(mut rows) => {
+                    let next_cursor = if options.after.is_some() && rows.len() as i64 > options.limit {
+                        rows.truncate(options.limit as usize);
+                        rows.last().map(|row| row.get::<_, i32>("id"))
+                    } else {
+                        None
+                    };
+
+                    let content = timing.record("serialize", || {
+                        let articles: Vec<Article> = rows.iter().map(|row| view_article(row, config, &options.view)).collect();
+                        // Cursor pages are wrapped so next_cursor can travel with the items
+                        match (&facets, options.after.is_some()) {
+                            (Some(sources), true) => render_json(&serde_json::json!({ "items": articles, "next_cursor": next_cursor, "facets": { "source": sources } }), &options.view),
+                            (Some(sources), false) => render_json(&serde_json::json!({ "items": articles, "facets": { "source": sources } }), &options.view),
+                            (None, true) => render_json(&serde_json::json!({ "items": articles, "next_cursor": next_cursor }), &options.view),
+                            (None, false) => render_json(&articles, &options.view),
+                        }
+                    });
+                    let links = match (total, next_cursor) {
+                        (Some(total), _) => get_pagination_links(request, options.limit, options.offset, total),
+                        (None, Some(cursor)) if config.pagination_links => {
+                            format!("<{}>; rel=\"next\"", get_page_url(request, &[("limit", options.limit), ("after", cursor as i64)]))
+                        }
+                        _ => String::new(),
+                    };
+                    let status_line = if links.is_empty() { OK_RESPONSE.to_string() } else { with_header(OK_RESPONSE, "Link", &links) };
+
+                    (status_line, content)
+                }
+                Err(e) => {
+                    log::error!("Error fetching articles: {}", e);
+                    error_response(INTERNAL_SERVER_ERROR, "Error fetching articles")
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    })
+}
+
+// Write a list response as its rows arrive: the head, then the JSON array one article at a time.
+// The status is already sent, so an error part way through can only be logged and the array cut short
+fn stream_articles(out: &mut dyn Write, status_line: &str, mut rows: postgres::RowIter, config: &Config, view: &ViewOptions) {
+    let mut write_rows = || -> Result<(), Box<dyn std::error::Error>> {
+        out.write_all(status_line.as_bytes())?;
+        out.write_all(b"[")?;
+        let mut first = true;
+        while let Some(row) = rows.next()? {
+            if !first {
+                out.write_all(b",")?;
+            }
+            first = false;
+            out.write_all(render_json(&view_article(&row, config, view), view).as_bytes())?;
+        }
+        out.write_all(b"]")?;
+        Ok(())
+    };
+    if let Err(e) = write_rows() {
+        log::error!("Error streaming articles: {}", e);
+    }
+}
+
+// Handle oEmbed request function: describes the article at ?url=/articles/:id as an oEmbed "link"
+fn handle_oembed_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    let provider_name = match &config.oembed_provider_name {
+        Some(name) => name,
+        None => return error_response(NOT_FOUND, "404 Not Found"),
+    };
+
+    let params = &request.query;
+    if params.get_str("format").is_some_and(|format| format != "json") {
+        return error_response("HTTP/1.1 501 NOT IMPLEMENTED\r\n\r\n", "Only the json oEmbed format is supported");
+    }
+
+    // The url may be a full URL or just the path; only the /articles/:id part matters
+    let id = match params
+        .get_str("url")
+        .and_then(|url| url.split("/articles/").nth(1))
+        .and_then(|rest| rest.split(['/', '?', '#']).next())
+        .and_then(|id| id.parse::<i32>().ok())
+    {
+        Some(id) => id,
+        None => return error_response(BAD_REQUEST, "url must point at /articles/:id"),
+    };
+
+    match pool.get() {
+        Ok(mut client) => match client.query_opt("SELECT title FROM articles WHERE id = $1", &[&id]) {
+            Ok(Some(row)) => {
+                let title: String = row.get(0);
+                let oembed = serde_json::json!({
+                    "version": "1.0",
+                    "type": "link",
+                    "title": title,
+                    "provider_name": provider_name,
+                });
+                (OK_RESPONSE.to_string(), oembed.to_string())
+            }
+            Ok(None) => error_response(NOT_FOUND, "Article not found"),
+            Err(e) => {
+                log::error!("Error fetching article: {}", e);
+                error_response(INTERNAL_SERVER_ERROR, "Error")
+            }
+        },
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle version request function: package version plus the git commit and build time recorded by build.rs
+fn handle_version_request(config: &Config) -> (String, String) {
+    if !config.version_endpoint {
+        return error_response(NOT_FOUND, "404 Not Found");
+    }
+
+    let built_at = env!("BUILD_TIMESTAMP").parse().ok().and_then(|secs| DateTime::<Utc>::from_timestamp(secs, 0));
+    let version = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("BUILD_GIT_SHA"),
+        "built_at": built_at,
+    });
+    (OK_RESPONSE.to_string(), version.to_string())
+}
+
+// Handle schema request function: JSON Schema of the article write shape so clients can validate before POSTing
+fn handle_schema_request(config: &Config) -> (String, String) {
+    if !config.article_schema {
+        return error_response(NOT_FOUND, "404 Not Found");
+    }
+
+    (
+        "HTTP/1.1 200 OK\r\nContent-Type: application/schema+json\r\n\r\n".to_string(),
+        article_schema(config).to_string(),
+    )
+}
+
+// Handle GeoJSON request function: geotagged articles as a FeatureCollection of Points for mapping tools
+fn handle_geojson_request(config: &Config, pool: &DbPool) -> (String, String) {
+    if !config.geojson_export {
+        return error_response(NOT_FOUND, "404 Not Found");
+    }
+
+    match pool.get() {
+        Ok(mut client) => {
+            let hide_sources = !config.hidden_sources.is_empty();
+            let query = format!(
+                "SELECT * FROM articles WHERE lat IS NOT NULL AND lon IS NOT NULL{} ORDER BY id",
+                if hide_sources { " AND source <> ALL($1)" } else { "" }
+            );
+            let params: Vec<&(dyn ToSql + Sync)> = if hide_sources { vec![&config.hidden_sources] } else { vec![] };
+
+            match client.query(&query, &params) {
+                Ok(rows) => {
+                    // GeoJSON positions are [longitude, latitude]
+                    let features: Vec<serde_json::Value> = rows
+                        .iter()
+                        .map(|row| row_to_article(row, config))
+                        .map(|article| serde_json::json!({
+                            "type": "Feature",
+                            "id": article.id,
+                            "geometry": { "type": "Point", "coordinates": [article.lon, article.lat] },
+                            "properties": { "title": article.title, "source": article.source },
+                        }))
+                        .collect();
+                    let collection = serde_json::json!({ "type": "FeatureCollection", "features": features });
+
+                    ("HTTP/1.1 200 OK\r\nContent-Type: application/geo+json\r\n\r\n".to_string(), collection.to_string())
+                }
+                Err(e) => {
+                    log::error!("Error fetching geotagged articles: {}", e);
+                    error_response(INTERNAL_SERVER_ERROR, "Error fetching articles")
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle RSS request function: the latest RSS_FEED_SIZE articles as an RSS 2.0 feed, or only one
+// source's for /feed/:source.xml, which is a 404 when the source has no (visible) articles
+fn handle_rss_request(request: &HttpRequest, config: &Config, pool: &DbPool, source: Option<&str>) -> (String, String) {
+    let source = source.map(|source| if config.normalize_source { normalize_source(source) } else { source.to_string() });
+
+    match pool.get() {
+        Ok(mut client) => {
+            let mut conditions = Vec::new();
+            let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+            if !config.hidden_sources.is_empty() {
+                params.push(&config.hidden_sources);
+                conditions.push(format!("source <> ALL(${})", params.len()));
+            }
+            if let Some(source) = &source {
+                params.push(source);
+                conditions.push(format!("source = ${}", params.len()));
+            }
+            let filter = if conditions.is_empty() { String::new() } else { format!(" WHERE {}", conditions.join(" AND ")) };
+            let query = format!("SELECT * FROM articles{} ORDER BY id DESC LIMIT {}", filter, RSS_FEED_SIZE);
+
+            match client.query(&query, &params) {
+                Ok(rows) if rows.is_empty() && source.is_some() => error_response(NOT_FOUND, "No articles found for the given source"),
+                Ok(rows) => {
+                    let articles: Vec<Article> = rows.iter().map(|row| row_to_article(row, config)).collect();
+                    (RSS_RESPONSE.to_string(), render_rss(&articles, request.header("Host"), source.as_deref()))
+                }
+                Err(e) => {
+                    log::error!("Error fetching articles for RSS: {}", e);
+                    error_response(INTERNAL_SERVER_ERROR, "Error fetching articles")
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Render articles as an RSS 2.0 document; the channel links to /articles on the requested host,
+// filtered to the source for a per-source feed
+fn render_rss(articles: &[Article], host: Option<&str>, source: Option<&str>) -> String {
+    let path = match source {
+        Some(source) => format!("/articles?source={}", source),
+        None => "/articles".to_string(),
+    };
+    let channel_link = host.map(|host| format!("http://{}{}", host, path)).unwrap_or(path);
+    let (title, description) = match source {
+        Some(source) => (format!("newsapi: {}", source), format!("Latest articles from {}", source)),
+        None => ("newsapi".to_string(), "Latest articles".to_string()),
+    };
+    let items: String = articles
+        .iter()
+        .map(|article| {
+            let link = article.url.as_deref().map(|url| format!("<link>{}</link>", escape_xml(url))).unwrap_or_default();
+            let pub_date = article.created_at.map(|date| format!("<pubDate>{}</pubDate>", date.to_rfc2822())).unwrap_or_default();
+            format!(
+                "<item><title>{}</title>{}<description>{}</description><guid isPermaLink=\"false\">{}</guid>{}</item>",
+                escape_xml(&article.title),
+                link,
+                escape_xml(&article.content),
+                article.id.unwrap_or_default(),
+                pub_date
+            )
+        })
+        .collect();
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\"><channel><title>{}</title><link>{}</link>\
+         <description>{}</description>{}</channel></rss>",
+        escape_xml(&title),
+        escape_xml(&channel_link),
+        escape_xml(&description),
+        items
+    )
+}
+
+// Escape the characters that are special in XML text and attribute values
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Handle sample request function: up to n random articles spread across sources roughly in
+// proportion to how many articles each source has
+fn handle_sample_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    let params = &request.query;
+    let (size, options) = match (params.get_i64("n"), ListOptions::from_query(params)) {
+        (Ok(Some(size)), _) if !(1..=SAMPLE_MAX_SIZE).contains(&size) => {
+            return error_response(BAD_REQUEST, &format!("Query parameter 'n' must be between 1 and {}", SAMPLE_MAX_SIZE));
+        }
+        (Ok(size), Ok(options)) => (size.unwrap_or(10), options),
+        (Err(e), _) | (_, Err(e)) => return error_response(BAD_REQUEST, &e.to_string()),
+    };
+
+    match pool.get() {
+        Ok(mut client) => {
+            let hide_sources = !config.hidden_sources.is_empty() && !options.include_hidden;
+            let (filter, limit) = if hide_sources { (" WHERE source <> ALL($1)", "$2") } else { ("", "$1") };
+            // Ordering by rank relative to source size interleaves sources proportionally
+            let query = format!(
+                "SELECT * FROM (SELECT *, ROW_NUMBER() OVER (PARTITION BY source ORDER BY random()) AS source_rank, \
+                 COUNT(*) OVER (PARTITION BY source) AS source_count FROM articles{}) AS ranked \
+                 ORDER BY source_rank::float8 / source_count, random() LIMIT {}",
+                filter, limit
+            );
+            let params: Vec<&(dyn ToSql + Sync)> = if hide_sources { vec![&config.hidden_sources, &size] } else { vec![&size] };
+
+            match client.query(&query, &params) {
+                Ok(rows) => {
+                    let articles: Vec<Article> = rows.iter().map(|row| view_article(row, config, &options.view)).collect();
+
+                    (OK_RESPONSE.to_string(), render_json(&articles, &options.view))
+                }
+                Err(e) => {
+                    log::error!("Error sampling articles: {}", e);
+                    error_response(INTERNAL_SERVER_ERROR, "Error")
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle since request function: a polling feed of the articles with an id above /articles/since/:id,
+// oldest first and at most ?limit of them, so a client can poll again with the last id it got
+fn handle_since_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    if !config.since_feed {
+        return error_response(NOT_FOUND, "404 Not Found");
+    }
+
+    let id = match request.segment(2).parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return error_response(BAD_REQUEST, "Invalid article id"),
+    };
+    let options = match ListOptions::from_query(&request.query) {
+        Ok(options) => options,
+        Err(e) => return error_response(BAD_REQUEST, &e.to_string()),
+    };
+
+    match pool.get() {
+        Ok(mut client) => {
+            let hide_sources = !config.hidden_sources.is_empty() && !options.include_hidden;
+            let query = format!(
+                "SELECT * FROM articles WHERE id > $1{} ORDER BY id LIMIT $2",
+                if hide_sources { " AND source <> ALL($3)" } else { "" }
+            );
+            let params: Vec<&(dyn ToSql + Sync)> =
+                if hide_sources { vec![&id, &options.limit, &config.hidden_sources] } else { vec![&id, &options.limit] };
+
+            match client.query(&query, &params) {
+                Ok(rows) => {
+                    let articles: Vec<Article> = rows.iter().map(|row| view_article(row, config, &options.view)).collect();
+
+                    (OK_RESPONSE.to_string(), render_json(&articles, &options.view))
+                }
+                Err(e) => {
+                    log::error!("Error fetching articles: {}", e);
+                    error_response(INTERNAL_SERVER_ERROR, "Error fetching articles")
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle search request function: case-insensitive substring match of ?q= on title or content, newest first,
+// or with ?order=relevance (SEARCH_ORDER sets the default) title-match-first: articles whose title matches,
+// then those matching only on content, newest first within each. Matches aren't scored beyond that.
+// Content encrypted with ENCRYPTION_KEY or compressed with COMPRESS_CONTENT can only match on title, so then
+// only the title is searched; X-Search-Fields says which fields were.
+fn handle_search_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    let params = &request.query;
+    let options = match ListOptions::from_query(params) {
+        Ok(options) => options,
+        Err(e) => return error_response(BAD_REQUEST, &e.to_string()),
+    };
+    let pattern = match params.get_str("q").map(str::trim).filter(|q| !q.is_empty()) {
+        Some(q) => format!("%{}%", escape_like(q)),
+        None => return error_response(BAD_REQUEST, "Query parameter 'q' is required"),
+    };
+    let order = match params.get_str("order") {
+        Some(order) => match SearchOrder::parse(order) {
+            Some(order) => order,
+            None => return error_response(BAD_REQUEST, &format!("Unsupported order '{}'; use relevance or recency", order)),
+        },
+        None => config.search_order,
+    };
+
+    match pool.get() {
+        Ok(mut client) => {
+            let hide_sources = !config.hidden_sources.is_empty() && !options.include_hidden;
+            let title_only = config.content_cipher.is_some() || config.compress_content;
+            let order_by = match order {
+                SearchOrder::Recency => "created_at DESC, id DESC",
+                SearchOrder::Relevance => "(title ILIKE $1) DESC, created_at DESC, id DESC",
+            };
+            let query = format!(
+                "SELECT * FROM articles WHERE {}{} ORDER BY {} LIMIT $2 OFFSET $3",
+                if title_only { "title ILIKE $1" } else { "(title ILIKE $1 OR content ILIKE $1)" },
+                if hide_sources { " AND source <> ALL($4)" } else { "" },
+                order_by
+            );
+            let mut params: Vec<&(dyn ToSql + Sync)> = vec![&pattern, &options.limit, &options.offset];
+            if hide_sources {
+                params.push(&config.hidden_sources);
+            }
+
+            match client.query(&query, &params) {
+                Ok(rows) => {
+                    let articles: Vec<Article> = rows.iter().map(|row| view_article(row, config, &options.view)).collect();
+                    let fields = if title_only { "title" } else { "title, content" };
+
+                    (with_header(OK_RESPONSE, "X-Search-Fields", fields), render_json(&articles, &options.view))
+                }
+                Err(e) => {
+                    log::error!("Error searching articles: {}", e);
+                    error_response(INTERNAL_SERVER_ERROR, "Error searching articles")
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle batch GET request function: the listing's view options and HIDDEN_SOURCES apply to the ids asked for
+fn handle_batch_get_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    let options = match ListOptions::from_query(&request.query) {
+        Ok(options) => options,
+        Err(e) => return error_response(BAD_REQUEST, &e.to_string()),
+    };
+    let batch = match serde_json::from_str::<BatchGetRequest>(request.body) {
+        Ok(batch) => batch,
+        Err(e) => return error_response(BAD_REQUEST, &format!("Invalid request body: {}", e)),
+    };
+    if batch.ids.len() > config.batch_get_max_ids {
+        return error_response(PAYLOAD_TOO_LARGE, &format!("At most {} ids per request", config.batch_get_max_ids));
+    }
+
+    match pool.get() {
+        Ok(mut client) => {
+            let hide_sources = !config.hidden_sources.is_empty() && !options.include_hidden;
+            let query = format!(
+                "SELECT * FROM articles WHERE id = ANY($1){} ORDER BY id",
+                if hide_sources { " AND source <> ALL($2)" } else { "" }
+            );
+            let mut params: Vec<&(dyn ToSql + Sync)> = vec![&batch.ids];
+            if hide_sources {
+                params.push(&config.hidden_sources);
+            }
+
+            match client.query(&query, &params) {
+                Ok(rows) => {
+                    let articles: Vec<Article> = rows.iter().map(|row| view_article(row, config, &options.view)).collect();
+
+                    (OK_RESPONSE.to_string(), render_json(&articles, &options.view))
+                }
+                Err(e) => {
+                    log::error!("Error fetching articles: {}", e);
+                    db_error_response(&e, "Error fetching articles")
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle import request function: copies another instance's articles, skipping ones already present
+fn handle_import_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    let body = request.body;
+    let import = match serde_json::from_str::<ImportRequest>(body) {
+        Ok(import) => import,
+        Err(e) => return error_response(BAD_REQUEST, &format!("Invalid request body: {}", e)),
+    };
+
+    if !is_allowed_url(&import.url, config) {
+        return error_response(FORBIDDEN, "URL host is not in the scrape allowlist");
+    }
+
+    let request_id = request.header("X-Request-Id");
+    let fetched = guarded_http_client(config)
+        .and_then(|client| with_request_id(client.get(&import.url), request_id).header("Accept", "application/json").send())
+        .and_then(|res| res.error_for_status())
+        .and_then(|res| res.text());
+    let text = match fetched {
+        Ok(text) => text,
+        Err(e) => {
+            log::error!("Error fetching URL: {}", e);
+            return error_response(INTERNAL_SERVER_ERROR, &format!("Error fetching URL: {}", e));
+        }
+    };
+
+    let articles = match serde_json::from_str::<ImportPayload>(&text) {
+        Ok(ImportPayload::List(articles)) | Ok(ImportPayload::Envelope { items: articles }) => articles,
+        Err(e) => return error_response(BAD_REQUEST, &format!("Remote response is not an article list: {}", e)),
+    };
+
+    let fetched = articles.len();
+    // Blank entries from the other instance are skipped rather than imported, and the rest are
+    // normalized before the category rules see them
+    let mut articles: Vec<Article> = articles
+        .into_iter()
+        .filter(|article| validate_article(article, config).is_ok())
+        .map(|mut article| {
+            if config.normalize_source {
+                article.source = normalize_source(&article.source);
+            }
+            article
+        })
+        .collect();
+
+    let mut client = match pool.get() {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            return error_response(INTERNAL_SERVER_ERROR, "Database connection error");
+        }
+    };
+    let mut transaction = match client.transaction() {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            log::error!("Error starting transaction: {}", e);
+            return db_error_response(&e, "Error starting transaction");
+        }
+    };
+
+    let newest_before = match lock_for_article_limit(&mut transaction, config) {
+        Ok(newest_before) => newest_before,
+        Err(e) => {
+            log::error!("Error checking article limit: {}", e);
+            return db_error_response(&e, "Error checking article limit");
+        }
+    };
+    if let Err(e) = apply_category_rules(&mut transaction, config, &mut articles) {
+        log::error!("Error applying category rules: {}", e);
+        return db_error_response(&e, "Error applying category rules");
+    }
+
+    let created = match insert_new_articles(&mut transaction, config, articles) {
+        Ok(created) => created,
+        Err(e) => {
+            log::error!("Error inserting article into database: {}", e);
+            return db_error_response(&e, "Error inserting article into database");
+        }
+    };
+
+    // Sized by the rows actually inserted, so entries already present don't evict anything
+    match trim_to_article_limit(&mut transaction, config, newest_before, created.len() as i64) {
+        Ok(true) => {}
+        Ok(false) => return error_response(INSUFFICIENT_STORAGE, "Article limit reached"),
+        Err(e) => {
+            log::error!("Error checking article limit: {}", e);
+            return db_error_response(&e, "Error checking article limit");
+        }
+    }
+    if let Err(e) = transaction.commit() {
+        log::error!("Error committing import: {}", e);
+        return db_error_response(&e, "Error committing import");
+    }
+
+    announce_articles(config, &created, request_id);
+    let inserted = created.len();
+
+    let summary = serde_json::json!({ "fetched": fetched, "inserted": inserted, "skipped": fetched - inserted });
+    (OK_RESPONSE.to_string(), summary.to_string())
+}
+
+// Insert each imported article that isn't stored yet, returning the ones that were inserted
+fn insert_new_articles(client: &mut impl Queries, config: &Config, articles: Vec<Article>) -> Result<Vec<Article>, PostgresError> {
+    let duplicate = if config.dedup_case_insensitive {
+        "lower(title) = lower($1::varchar) AND lower(source) = lower($3::varchar)"
+    } else {
+        "title = $1::varchar AND source = $3::varchar"
+    };
+    let query = format!(
+        "INSERT INTO articles (title, content, source, image_url, lat, lon, url, normalized_url, category)
+         SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9 WHERE NOT EXISTS (SELECT 1 FROM articles WHERE {})
+         ON CONFLICT DO NOTHING RETURNING id, position, created_at, updated_at",
+        duplicate
+    );
+
+    let mut created = Vec::new();
+    for mut article in articles {
+        let row = client.query_opt(
+            &query,
+            &[
+                &article.title,
+                &encrypt_content(config, &article.content),
+                &article.source,
+                &article.image_url,
+                &article.lat,
+                &article.lon,
+                &article.url,
+                &normalize_url(config, article.url.as_deref()),
+                &article.category,
+            ]
+        )?;
+
+        if let Some(row) = row {
+            article.id = row.get(0);
+            article.position = row.get(1);
+            article.created_at = row.get(2);
+            article.updated_at = row.get(3);
+            created.push(article);
+        }
+    }
+
+    Ok(created)
+}
+
+// Handle PUT request function
+fn handle_put_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    match (
+        request.segment(1).parse::<i32>(),
+        get_valid_article(request, config),
+        pool.get(),
+    ) {
+        (Ok(id), Ok(mut article), Ok(mut client)) => {
+            if config.normalize_source {
+                article.source = normalize_source(&article.source);
+            }
+
+            // With PUT_NOT_MODIFIED a PUT that would change nothing gets a 304 and no write, so updated_at stays put
+            if config.put_not_modified {
+                match client.query_opt("SELECT * FROM articles WHERE id = $1", &[&id]) {
+                    Ok(Some(row)) if has_same_fields(&row_to_article(&row, config), &article) => {
+                        return (NOT_MODIFIED.to_string(), String::new());
+                    }
+                    Ok(Some(_)) => {}
+                    Ok(None) => return error_response(NOT_FOUND, "Article not found"),
+                    Err(e) => {
+                        log::error!("Error fetching article: {}", e);
+                        return db_error_response(&e, "Error");
+                    }
+                }
+            }
+
+            let updated = client.execute(
+                "UPDATE articles SET title = $1, content = $2, source = $3, image_url = $4, lat = $5, lon = $6, url = $7,
+                 normalized_url = $8, category = $9, updated_at = now() WHERE id = $10",
+                &[
+                    &article.title,
+                    &encrypt_content(config, &article.content),
+                    &article.source,
+                    &article.image_url,
+                    &article.lat,
+                    &article.lon,
+                    &article.url,
+                    &normalize_url(config, article.url.as_deref()),
+                    &article.category,
+                    &id,
+                ]
+            );
+
+            match updated {
+                Ok(0) => error_response(NOT_FOUND, "Article not found"),
+                Ok(_) => message_response("Article updated"),
+                Err(e) => {
+                    log::error!("Error updating article: {}", e);
+                    db_error_response(&e, "Error")
+                }
+            }
+        }
+        (Err(_), _, _) => error_response(BAD_REQUEST, "Invalid article id"),
+        (_, Err(message), _) => error_response(BAD_REQUEST, &message),
+        (_, _, Err(e)) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Whether a PUT body matches the stored article in every field PUT writes
+fn has_same_fields(stored: &Article, submitted: &Article) -> bool {
+    stored.title == submitted.title
+        && stored.content == submitted.content
+        && stored.source == submitted.source
+        && stored.image_url == submitted.image_url
+        && stored.lat == submitted.lat
+        && stored.lon == submitted.lon
+        && stored.url == submitted.url
+        && stored.category == submitted.category
+}
+
+// Handle PATCH request function: applies an RFC 6902 JSON Patch to the article
+fn handle_patch_request(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    let content_type = request.header("Content-Type").unwrap_or_default();
+    let body = request.body;
+
+    // RFC 6902 operations, or a partial article (RFC 7396 merge patch or plain JSON)
+    let document = if content_type.starts_with("application/json-patch+json") {
+        match serde_json::from_str::<Vec<PatchOperation>>(body) {
+            Ok(operations) => PatchDocument::JsonPatch(operations),
+            Err(e) => return error_response(UNPROCESSABLE_ENTITY, &format!("Invalid JSON Patch document: {}", e)),
+        }
+    } else if content_type.starts_with("application/merge-patch+json") || content_type.starts_with("application/json") {
+        match serde_json::from_str::<ArticleChanges>(body) {
+            Ok(changes) => PatchDocument::Changes(changes),
+            Err(e) => return error_response(BAD_REQUEST, &format!("Invalid article changes: {}", e)),
+        }
+    } else {
+        return error_response(
+            UNSUPPORTED_MEDIA_TYPE,
+            "PATCH requires Content-Type: application/json-patch+json, application/merge-patch+json or application/json",
+        );
+    };
+
+    match (request.segment(1).parse::<i32>(), pool.get()) {
+        (Ok(id), Ok(mut client)) =>
+            match patch_article(&mut client, config, id, &document) {
+                Ok(response) => response,
+                Err(e) => {
+                    log::error!("Error patching article: {}", e);
+                    db_error_response(&e, "Error")
+                }
+            }
+
+        (Err(_), _) => error_response(BAD_REQUEST, "Invalid article id"),
+        (_, Err(e)) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Load, patch and save an article inside a transaction
+fn patch_article(client: &mut Connection, config: &Config, id: i32, document: &PatchDocument) -> Result<(String, String), PostgresError> {
+    let mut transaction = client.transaction()?;
+
+    let mut article = match transaction.query_opt("SELECT * FROM articles WHERE id = $1 FOR UPDATE", &[&id])? {
+        Some(row) => row_to_article(&row, config),
+        None => return Ok(error_response(NOT_FOUND, "Article not found")),
+    };
+
+    let applied = match document {
+        PatchDocument::JsonPatch(operations) => apply_patch(&mut article, operations),
+        PatchDocument::Changes(changes) => {
+            apply_changes(&mut article, changes);
+            Ok(())
+        }
+    };
+    if let Err(message) = applied.and_then(|_| validate_article(&article, config)) {
+        return Ok(error_response(UNPROCESSABLE_ENTITY, &message));
+    }
+
+    if config.normalize_source {
+        article.source = normalize_source(&article.source);
+    }
+
+    let row = transaction.query_one(
+        "UPDATE articles SET title = $1, content = $2, source = $3, updated_at = now() WHERE id = $4 RETURNING updated_at",
+        &[&article.title, &encrypt_content(config, &article.content), &article.source, &id]
+    )?;
+    article.updated_at = row.get(0);
+    transaction.commit()?;
+
+    Ok((OK_RESPONSE.to_string(), serde_json::to_string(&article).unwrap()))
+}
+
+// Overwrite the fields present in a partial article, leaving the rest untouched
+fn apply_changes(article: &mut Article, changes: &ArticleChanges) {
+    if let Some(title) = &changes.title {
+        article.title = title.clone();
+    }
+    if let Some(content) = &changes.content {
+        article.content = content.clone();
+    }
+    if let Some(source) = &changes.source {
+        article.source = source.clone();
+    }
+}
+
+// Apply add/replace/remove operations on /title, /content and /source
+fn apply_patch(article: &mut Article, operations: &[PatchOperation]) -> Result<(), String> {
+    for operation in operations {
+        let field = match operation.path.as_str() {
+            "/title" => &mut article.title,
+            "/content" => &mut article.content,
+            "/source" => &mut article.source,
+            path => return Err(format!("Unsupported patch path: {}", path)),
+        };
+
+        match operation.op.as_str() {
+            "add" | "replace" => match &operation.value {
+                Some(serde_json::Value::String(value)) => *field = value.clone(),
+                _ => return Err(format!("{} on {} requires a string value", operation.op, operation.path)),
+            },
+            // title and source are required, so only content can be removed
+            "remove" if operation.path == "/content" => field.clear(),
+            "remove" => return Err(format!("{} is required and cannot be removed", operation.path)),
+            op => return Err(format!("Unsupported patch operation: {}", op)),
+        }
+    }
+
+    Ok(())
+}
+
+// Handle DELETE request function
+fn handle_delete_request(request: &HttpRequest, pool: &DbPool) -> (String, String) {
+    match (request.segment(1).parse::<i32>(), pool.get()) {
+        (Ok(id), Ok(mut client)) => {
+            let rows_affected = match client.execute("DELETE FROM articles WHERE id = $1", &[&id]) {
+                Ok(rows_affected) => rows_affected,
+                Err(e) => {
+                    log::error!("Error deleting article: {}", e);
+                    return db_error_response(&e, "Error");
+                }
+            };
+
+            if rows_affected == 0 {
+                return error_response(NOT_FOUND, "Article not found");
+            }
+
+            message_response("Article deleted")
+        }
+        (Err(_), _) => error_response(BAD_REQUEST, "Invalid article id"),
+        (_, Err(e)) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle move request function
+fn handle_move_request(request: &HttpRequest, pool: &DbPool) -> (String, String) {
+    match (
+        request.segment(1).parse::<i32>(),
+        request.segment(3).parse::<i32>(),
+        pool.get(),
+    ) {
+        (Ok(id), Ok(new_id), Ok(mut client)) =>
+            match move_article(&mut client, id, new_id) {
+                Ok(response) => response,
+                Err(e) => {
+                    log::error!("Error moving article: {}", e);
+                    db_error_response(&e, "Error")
+                }
+            }
+
+        (Err(_), _, _) => error_response(BAD_REQUEST, "Invalid article id"),
+        (_, Err(_), _) => error_response(BAD_REQUEST, "Invalid target article id"),
+        (_, _, Err(e)) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Change an article's primary key inside a transaction
+fn move_article(client: &mut Connection, id: i32, new_id: i32) -> Result<(String, String), PostgresError> {
+    let mut transaction = client.transaction()?;
+
+    if transaction.query_opt("SELECT 1 FROM articles WHERE id = $1 FOR UPDATE", &[&id])?.is_none() {
+        return Ok(error_response(NOT_FOUND, "Article not found"));
+    }
+
+    if transaction.query_opt("SELECT 1 FROM articles WHERE id = $1", &[&new_id])?.is_some() {
+        return Ok(error_response(CONFLICT, "Target id is already taken"));
+    }
+
+    transaction.execute("UPDATE articles SET id = $1 WHERE id = $2", &[&new_id, &id])?;
+
+    // Keep the id sequence ahead of the new id so later inserts don't collide with it
+    transaction.execute(
+        "SELECT setval(pg_get_serial_sequence('articles', 'id'), GREATEST($1, last_value)) FROM articles_id_seq",
+        &[&i64::from(new_id)]
+    )?;
+
+    transaction.commit()?;
+
+    Ok(message_response("Article moved"))
+}
+
+// Handle promote/demote request function
+fn handle_reorder_request(request: &HttpRequest, pool: &DbPool, promote: bool) -> (String, String) {
+    match (request.segment(1).parse::<i32>(), pool.get()) {
+        (Ok(id), Ok(mut client)) =>
+            match swap_with_neighbor(&mut client, id, promote) {
+                Ok(response) => response,
+                Err(e) => {
+                    log::error!("Error reordering article: {}", e);
+                    db_error_response(&e, "Error")
+                }
+            }
+
+        (Err(_), _) => error_response(BAD_REQUEST, "Invalid article id"),
+        (_, Err(e)) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Swap an article's position with the one just before (promote) or after (demote) it
+fn swap_with_neighbor(client: &mut Connection, id: i32, promote: bool) -> Result<(String, String), PostgresError> {
+    let mut transaction = client.transaction()?;
+
+    let position: i32 = match transaction.query_opt("SELECT position FROM articles WHERE id = $1 FOR UPDATE", &[&id])? {
+        Some(row) => row.get(0),
+        None => return Ok(error_response(NOT_FOUND, "Article not found")),
+    };
+
+    let neighbor_query = if promote {
+        "SELECT id, position FROM articles WHERE position < $1 ORDER BY position DESC LIMIT 1 FOR UPDATE"
+    } else {
+        "SELECT id, position FROM articles WHERE position > $1 ORDER BY position ASC LIMIT 1 FOR UPDATE"
+    };
+
+    let neighbor = match transaction.query_opt(neighbor_query, &[&position])? {
+        Some(row) => row,
+        None => return Ok(message_response("Article is already at the end of the list")),
+    };
+    let neighbor_id: i32 = neighbor.get(0);
+    let neighbor_position: i32 = neighbor.get(1);
+
+    transaction.execute("UPDATE articles SET position = $1 WHERE id = $2", &[&neighbor_position, &id])?;
+    transaction.execute("UPDATE articles SET position = $1 WHERE id = $2", &[&position, &neighbor_id])?;
+    transaction.commit()?;
+
+    Ok(message_response("Article reordered"))
+}
+
+// Handle scrape function: runs the scraper registered for the source in /scrape/:source
+fn handle_scrape(request: &HttpRequest, state: &AppState, refresh: bool) -> (String, String) {
+    let scraper = match find_scraper(request.segment(1)) {
+        Some(scraper) => scraper,
+        None => return error_response(NOT_FOUND, "Unknown scrape source"),
+    };
+
+    let options = match ScrapeOptions::from_query(&request.query, refresh) {
+        Ok(options) => options,
+        Err(e) => return error_response(BAD_REQUEST, &e.to_string()),
+    };
+    // A refresh deletes every stored item first, so there's no boundary left to stop at
+    if options.refresh && options.incremental {
+        return error_response(BAD_REQUEST, "incremental can't be combined with refresh");
+    }
+    let _slot = match state.scrape_slots.try_claim() {
+        Some(slot) => slot,
+        None => return state.scrape_slots.busy_response("scrapes"),
+    };
+
+    match scrape_source(scraper, state, &options, request.header("X-Request-Id")) {
+        Ok(summary) => (OK_RESPONSE.to_string(), serde_json::to_string(&summary).unwrap()),
+        Err(response) => response,
+    }
+}
+
+// Handle scrape all function: scrapes every registered source in turn with the same query options
+// (refresh excepted) and answers with one result per source; a source that fails, or is still
+// cooling down, gets its error status and message in the report and the others carry on
+fn handle_scrape_all(request: &HttpRequest, state: &AppState) -> (String, String) {
+    if !state.config.scrape_all {
+        return error_response(NOT_FOUND, "404 Not Found");
+    }
+
+    let options = match ScrapeOptions::from_query(&request.query, false) {
+        Ok(options) => options,
+        Err(e) => return error_response(BAD_REQUEST, &e.to_string()),
+    };
+    // The sources are scraped one after another, so the whole run takes one slot
+    let _slot = match state.scrape_slots.try_claim() {
+        Some(slot) => slot,
+        None => return state.scrape_slots.busy_response("scrapes"),
+    };
+
+    let mut failed = 0;
+    let results: Vec<serde_json::Value> = all_scrapers()
+        .iter()
+        .map(|scraper| match scrape_source(*scraper, state, &options, request.header("X-Request-Id")) {
+            Ok(summary) => {
+                let mut result = serde_json::to_value(&summary).unwrap();
+                result["status"] = serde_json::json!(200);
+                result
+            }
+            Err((status_line, content)) => {
+                failed += 1;
+                let status = status_line.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok());
+                let error = serde_json::from_str::<serde_json::Value>(&content).ok().and_then(|body| body.get("error").cloned());
+                serde_json::json!({ "source": scraper.source(), "status": status, "error": error })
+            }
+        })
+        .collect();
+
+    let report = serde_json::json!({ "results": results, "succeeded": results.len() - failed, "failed": failed });
+    (OK_RESPONSE.to_string(), report.to_string())
+}
+
+// Scrape one source unless it is still within its cooldown, in which case the error is a 429
+fn scrape_source(scraper: &dyn Scraper, state: &AppState, options: &ScrapeOptions, request_id: Option<&str>) -> Result<ScrapeSummary, (String, String)> {
+    if let Err(retry_after) = start_scrape_cooldown(state, scraper.source()) {
+        return Err(error_response(
+            &format!("HTTP/1.1 429 TOO MANY REQUESTS\r\nRetry-After: {}\r\n\r\n", retry_after),
+            &format!("Source {} was scraped recently; retry in {} seconds", scraper.source(), retry_after),
+        ));
+    }
+
+    run_scrape(scraper, &state.config, &state.db, options, request_id)
+}
+
+// Keep the scraped articles whose trimmed title has at least SCRAPE_MIN_TITLE_LEN characters
+fn drop_short_titles(articles: Vec<Article>, min_title_len: usize) -> Vec<Article> {
+    articles.into_iter().filter(|article| article.title.trim().chars().count() >= min_title_len).collect()
+}
+
+// Fetch a source's list page, parse it with its scraper and insert the articles in one transaction,
+// returning a summary or the error response to send. With refresh the source's existing rows are deleted
+// in that same transaction, so a failed fetch or insert leaves them untouched
+fn run_scrape(scraper: &dyn Scraper, config: &Config, pool: &DbPool, options: &ScrapeOptions, request_id: Option<&str>) -> Result<ScrapeSummary, (String, String)> {
+    let count = options.count;
+    log::info!("Starting {} scrape...", scraper.source());
+    let started = Instant::now();
+
+    // SCRAPE_TIMEOUT_SECS bounds the whole fetch, body included, so a hanging source can't hold the request
+    let client = match reqwest::blocking::Client::builder().timeout(Duration::from_secs(config.scrape_timeout_secs)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Error building HTTP client: {}", e);
+            return Err(error_response(INTERNAL_SERVER_ERROR, "Error building HTTP client"));
+        }
+    };
+    let response = match with_request_id(client.get(scraper.list_url(count)), request_id)
+        .header("User-Agent", USER_AGENT)
+        .send() {
+        Ok(res) => {
+            log::info!("Received response from {}", scraper.source());
+            match res.text() {
+                Ok(text) => text,
+                Err(e) if e.is_timeout() => {
+                    log::error!("Timed out reading response from {}: {}", scraper.source(), e);
+                    return Err(error_response(GATEWAY_TIMEOUT, &format!("{} did not respond within {} seconds", scraper.source(), config.scrape_timeout_secs)));
+                },
+                Err(e) => {
+                    log::error!("Error reading response text: {}", e);
+                    return Err(error_response(INTERNAL_SERVER_ERROR, "Error reading response text"));
+                },
+            }
+        },
+        Err(e) if e.is_timeout() => {
+            log::error!("Timed out fetching {}: {}", scraper.source(), e);
+            return Err(error_response(GATEWAY_TIMEOUT, &format!("{} did not respond within {} seconds", scraper.source(), config.scrape_timeout_secs)));
+        },
+        Err(e) => {
+            log::error!("Error fetching URL: {}", e);
+            return Err(error_response(INTERNAL_SERVER_ERROR, &format!("Error fetching URL: {}", e)));
+        },
+    };
+
+    let articles = match scraper.parse(&Html::parse_document(&response), config, count) {
+        Ok(articles) => articles,
+        Err(e) => {
+            log::error!("Error parsing {} page: {}", scraper.source(), e);
+            return Err(error_response(INTERNAL_SERVER_ERROR, &e.to_string()));
+        }
+    };
+    // Stray empty or one-character anchors are counted as fetched but skipped
+    let fetched = articles.len();
+    let mut articles = drop_short_titles(articles, config.scrape_min_title_len);
+    let source = if config.normalize_source { normalize_source(scraper.source()) } else { scraper.source().to_string() };
+    let raw_html = if options.store_raw {
+        Some(truncate_to_char_boundary(&response, config.raw_html_max_bytes).to_string())
+    } else {
+        None
+    };
+    // A page that parses to nothing most likely means the layout changed; don't wipe the source for it
+    if options.refresh && articles.is_empty() {
+        return Err(error_response(INTERNAL_SERVER_ERROR, &format!("No articles found on the {} page; existing entries kept", scraper.source())));
+    }
+
+    match pool.get() {
+        Ok(mut client) => {
+            let mut transaction = match client.transaction() {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    log::error!("Error starting transaction: {}", e);
+                    return Err(db_error_response(&e, "Error starting transaction"));
+                }
+            };
+            let newest_before = match lock_for_article_limit(&mut transaction, config) {
+                Ok(newest_before) => newest_before,
+                Err(e) => {
+                    log::error!("Error checking article limit: {}", e);
+                    return Err(db_error_response(&e, "Error checking article limit"));
+                }
+            };
+
+            let replaced = if options.refresh {
+                match transaction.execute("DELETE FROM articles WHERE source = $1", &[&source]) {
+                    Ok(deleted) => Some(deleted),
+                    Err(e) => {
+                        log::error!("Error deleting {} articles: {}", source, e);
+                        return Err(db_error_response(&e, "Error deleting existing articles"));
+                    }
+                }
+            } else {
+                None
+            };
+
+            if let Err(e) = apply_category_rules(&mut transaction, config, &mut articles) {
+                log::error!("Error applying category rules: {}", e);
+                return Err(db_error_response(&e, "Error applying category rules"));
+            }
+            if let Some(category) = scraper.default_category() {
+                for article in articles.iter_mut() {
+                    article.category.get_or_insert_with(|| category.to_string());
+                }
+            }
+
+            let mut created = Vec::new();
+            let mut stopped_at = None;
+
+            for mut article in articles {
+                article.source = source.clone();
+                article.raw_html = raw_html.clone();
+
+                // Titles already stored for the source conflict with the (title, source) unique index, or its
+                // lowercased variant with DEDUP_CASE_INSENSITIVE, and are skipped; an incremental scrape stops
+                // at the first one, taking it as the point where the previous run left off
+                match transaction.query_opt(
+                    "INSERT INTO articles (title, content, source, raw_html, image_url, published_at, url, normalized_url, category)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) ON CONFLICT DO NOTHING RETURNING id, position, created_at, updated_at",
+                    &[
+                        &article.title,
+                        &encrypt_content(config, &article.content),
+                        &article.source,
+                        &article.raw_html,
+                        &article.image_url,
+                        &article.published_at,
+                        &article.url,
+                        &normalize_url(config, article.url.as_deref()),
+                        &article.category,
+                    ]
+                ) {
+                    Ok(None) if options.incremental => {
+                        stopped_at = Some(article.title);
+                        break;
+                    }
+                    Ok(None) => {}
+                    Ok(Some(row)) => {
+                        article.id = row.get(0);
+                        article.position = row.get(1);
+                        article.created_at = row.get(2);
+                        article.updated_at = row.get(3);
+                        created.push(article);
+                    }
+                    Err(e) => {
+                        log::error!("Error inserting article into database: {}", e);
+                        return Err(db_error_response(&e, "Error inserting article into database"));
+                    }
+                }
+            }
+
+            // Sized by the rows actually inserted, so titles skipped as already stored don't evict anything
+            match trim_to_article_limit(&mut transaction, config, newest_before, created.len() as i64) {
+                Ok(true) => {}
+                Ok(false) => return Err(error_response(INSUFFICIENT_STORAGE, "Article limit reached")),
+                Err(e) => {
+                    log::error!("Error checking article limit: {}", e);
+                    return Err(db_error_response(&e, "Error checking article limit"));
+                }
+            }
+
+            if let Err(e) = transaction.commit() {
+                log::error!("Error committing scrape: {}", e);
+                return Err(db_error_response(&e, "Error committing scrape"));
+            }
+
+            announce_articles(config, &created, request_id);
+            let inserted = created.len();
+
+            log::info!("Scraping completed successfully");
+            Ok(ScrapeSummary {
+                source,
+                fetched,
+                inserted,
+                skipped: fetched - inserted,
+                replaced,
+                stopped_at,
+                duration_ms: started.elapsed().as_millis(),
+            })
+        }
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            Err(error_response(INTERNAL_SERVER_ERROR, "Database connection error"))
+        }
+    }
+}
+
+// Take one request from the client's RATE_LIMIT_PER_MINUTE budget, or return the seconds until the
+// next one is available; always succeeds when rate limiting is off
+fn take_rate_limit_token(state: &AppState, peer: IpAddr) -> Result<(), u64> {
+    let limit = match state.config.rate_limit_per_minute {
+        Some(limit) => f64::from(limit),
+        None => return Ok(()),
+    };
+    let refill_per_sec = limit / 60.0;
+    let now = Instant::now();
+
+    let key = rate_limit_key(peer);
+    let mut limits = state.rate_limits.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    // A new client needs room in a full map. Idle clients have refilled completely, so forgetting them
+    // changes nothing; the sweep runs at most once per RATE_LIMIT_SWEEP_INTERVAL so a map full of active
+    // clients isn't scanned on every request, and until it frees room new clients are turned away
+    if limits.buckets.len() >= RATE_LIMIT_MAX_CLIENTS && !limits.buckets.contains_key(&key) {
+        if now.duration_since(limits.swept) >= RATE_LIMIT_SWEEP_INTERVAL {
+            limits.buckets.retain(|_, bucket| now.duration_since(bucket.updated).as_secs_f64() * refill_per_sec + bucket.tokens < limit);
+            limits.swept = now;
+            if limits.buckets.len() >= RATE_LIMIT_MAX_CLIENTS {
+                log::warn!("{} clients are being rate limited; turning new ones away", RATE_LIMIT_MAX_CLIENTS);
+            }
+        }
+        if limits.buckets.len() >= RATE_LIMIT_MAX_CLIENTS {
+            return Err(RATE_LIMIT_SWEEP_INTERVAL.as_secs());
+        }
+    }
+
+    limits.buckets.entry(key).or_insert(RateBucket { tokens: limit, updated: now }).take(limit, now)
+}
+
+// The rate limit bucket a client shares: its own address, except that IPv6 clients are grouped by their
+// /64 prefix, which a single host can otherwise rotate through freely
+fn rate_limit_key(peer: IpAddr) -> IpAddr {
+    match peer {
+        IpAddr::V6(addr) => match addr.to_ipv4_mapped() {
+            Some(addr) => IpAddr::V4(addr),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(addr) & !u128::from(u64::MAX))),
+        },
+        IpAddr::V4(_) => peer,
+    }
+}
+
+// With IDEMPOTENCY_KEYS, a POST carrying an Idempotency-Key runs once per key: a retry with the same
+// key and body gets the first response back, marked Idempotent-Replayed, the same key with another
+// body is a 422, and a retry arriving while the first is still running is a 409. Server errors
+// aren't kept, so a retry after one runs the request again
+fn with_idempotency(request: &HttpRequest, state: &AppState, handle: impl FnOnce() -> (String, String)) -> (String, String) {
+    let config = &state.config;
+    let key = match request.header("Idempotency-Key") {
+        Some(key) if config.idempotency_keys && !key.is_empty() => key,
+        _ => return handle(),
+    };
+    if key.len() > IDEMPOTENCY_KEY_MAX_LEN {
+        return error_response(BAD_REQUEST, &format!("Idempotency-Key must be at most {} characters", IDEMPOTENCY_KEY_MAX_LEN));
+    }
+    let key_hash = hash_str(key);
+    let body_hash = hash_str(request.body);
+    let ttl = Duration::from_secs(config.idempotency_ttl_secs);
+
+    let tracked = {
+        let mut keys = state.idempotency_keys.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        if keys.len() >= IDEMPOTENCY_MAX_KEYS {
+            keys.retain(|_, entry| now.duration_since(entry.stored) < ttl);
+        }
+
+        match keys.get(&key_hash) {
+            Some(entry) if now.duration_since(entry.stored) < ttl => {
+                if entry.body_hash != body_hash {
+                    return error_response(UNPROCESSABLE_ENTITY, "Idempotency-Key was already used with a different request body");
+                }
+                return match &entry.response {
+                    Some((status_line, content)) => (with_header(status_line, "Idempotent-Replayed", "true"), content.clone()),
+                    None => error_response(CONFLICT, "A request with this Idempotency-Key is still being processed"),
+                };
+            }
+            _ if keys.len() >= IDEMPOTENCY_MAX_KEYS => false,
+            _ => {
+                keys.insert(key_hash, IdempotentResponse { body_hash, stored: now, response: None });
+                true
+            }
+        }
+    };
+    if !tracked {
+        log::warn!("{} idempotency keys are stored; handling the request without one", IDEMPOTENCY_MAX_KEYS);
+        return handle();
+    }
+
+    let response = handle();
+    let mut keys = state.idempotency_keys.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if response.0.starts_with("HTTP/1.1 5") {
+        keys.remove(&key_hash);
+    } else if let Some(entry) = keys.get_mut(&key_hash) {
+        entry.response = Some(response.clone());
+    }
+    response
+}
+
+// Record a scrape of the source, or return the seconds left when it is still cooling down
+fn start_scrape_cooldown(state: &AppState, source: &str) -> Result<(), u64> {
+    let cooldown = state.config.scrape_cooldown(source);
+    if cooldown.is_zero() {
+        return Ok(());
+    }
+
+    // A panic elsewhere while holding the lock leaves the map usable, so recover it instead of panicking too
+    let mut last_runs = state.scrape_last_run.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(remaining) = last_runs.get(source).and_then(|last| cooldown.checked_sub(last.elapsed())) {
+        return Err(remaining.as_secs() + 1);
+    }
+
+    last_runs.insert(source.to_string(), Instant::now());
+    Ok(())
+}
+
+// Handle scrape test function: runs a selector against a page without inserting anything
+fn handle_scrape_test(request: &HttpRequest, config: &Config) -> (String, String) {
+    let body = request.body;
+    let test = match serde_json::from_str::<ScrapeTestRequest>(body) {
+        Ok(test) => test,
+        Err(e) => return error_response(BAD_REQUEST, &format!("Invalid request body: {}", e)),
+    };
+
+    if !is_allowed_url(&test.url, config) {
+        return error_response(FORBIDDEN, "URL host is not in the scrape allowlist");
+    }
+
+    let selector = match Selector::parse(&test.selector) {
+        Ok(sel) => sel,
+        Err(e) => return error_response(BAD_REQUEST, &format!("Invalid selector: {}", e)),
+    };
+
+    let client = match guarded_http_client(config) {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Error building HTTP client: {}", e);
+            return error_response(INTERNAL_SERVER_ERROR, "Error building HTTP client");
+        }
+    };
+    let outbound = with_request_id(client.get(&test.url), request.header("X-Request-Id"));
+    let response = match outbound.header("User-Agent", USER_AGENT).send().and_then(|res| res.text()) {
+        Ok(text) => text,
+        Err(e) => {
+            log::error!("Error fetching URL: {}", e);
+            return error_response(INTERNAL_SERVER_ERROR, &format!("Error fetching URL: {}", e));
+        }
+    };
+
+    let document = Html::parse_document(&response);
+    let matches: Vec<String> = document
+        .select(&selector)
+        .map(|x| x.text().collect::<String>().trim().to_string())
+        .collect();
+
+    (OK_RESPONSE.to_string(), serde_json::to_string(&matches).unwrap())
+}
+
+// Handle delete by source function: the path segment is percent-decoded and, with NORMALIZE_SOURCE,
+// normalized the way sources are stored
+fn handle_delete_by_source(config: &Config, pool: &DbPool, source: &str) -> (String, String) {
+    let source = source_from_path(config, source);
+
+    match pool.get() {
+        Ok(mut client) => {
+            let rows_affected = match client.execute("DELETE FROM articles WHERE source = $1", &[&source]) {
+                Ok(rows_affected) => rows_affected,
+                Err(e) => {
+                    log::error!("Error deleting articles: {}", e);
+                    return db_error_response(&e, "Error deleting articles");
+                }
+            };
+
+            if rows_affected == 0 {
+                return error_response(NOT_FOUND, "No articles found for the given source");
+            }
+
+            message_response("Articles deleted")
+        }
+        Err(_) => error_response(INTERNAL_SERVER_ERROR, "Database connection error"),
+    }
+}
+
+// Handle delete all request function: empties the articles table, but only with ?confirm=true
+fn handle_delete_all_request(request: &HttpRequest, pool: &DbPool) -> (String, String) {
+    match request.query.get_flag("confirm") {
+        Ok(true) => {}
+        Ok(false) => return error_response(BAD_REQUEST, "Deleting every article requires ?confirm=true"),
+        Err(e) => return error_response(BAD_REQUEST, &e.to_string()),
+    }
+
+    match pool.get() {
+        Ok(mut client) => match client.execute("DELETE FROM articles", &[]) {
+            Ok(deleted) => {
+                log::info!("Deleted all {} articles", deleted);
+                (OK_RESPONSE.to_string(), serde_json::json!({ "deleted": deleted }).to_string())
+            }
+            Err(e) => {
+                log::error!("Error deleting articles: {}", e);
+                db_error_response(&e, "Error deleting articles")
+            }
+        },
+        Err(_) => error_response(INTERNAL_SERVER_ERROR, "Database connection error"),
+    }
+}
+
+// Handle article stream function: GET /articles/stream answers with Server-Sent Events, an "article"
+// event for each article created after connecting plus a comment every SSE_KEEPALIVE_SECS so idle
+// proxies don't drop it. Runs until the client goes away or the server shuts down
+fn handle_article_stream(stream: &mut impl Write, state: &AppState) {
+    let config = &state.config;
+    // Subscribe before answering so nothing inserted in between is missed
+    let events = events::subscribe();
+    let head = with_cors_headers(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        config,
+    );
+    // retry tells EventSource how many milliseconds to wait before reconnecting
+    if stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(b"retry: 3000\n\n")).is_err() {
+        return;
+    }
+    log::info!("Article stream opened ({} subscribers)", events::subscriber_count());
+
+    let keepalive = Duration::from_secs(config.sse_keepalive_secs);
+    let mut last_write = Instant::now();
+    // Wake up every second to notice a shutdown even when nothing is being inserted
+    while !state.shutting_down.load(Ordering::SeqCst) {
+        let message = match events.recv_timeout(Duration::from_secs(1)) {
+            Ok(event) => format!("event: article\nid: {}\ndata: {}\n\n", event.id, event.data),
+            Err(RecvTimeoutError::Timeout) if last_write.elapsed() >= keepalive => ": keepalive\n\n".to_string(),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        if let Err(e) = stream.write_all(message.as_bytes()).and_then(|_| stream.flush()) {
+            log::info!("Article stream closed: {}", e);
+            return;
+        }
+        last_write = Instant::now();
+    }
+}
+
+// Handle admin status function: connection counters and connection pool usage
+fn handle_admin_status(request: &HttpRequest, state: &AppState) -> (String, String) {
+    if let Err(response) = check_admin_key(request, &state.config) {
+        return response;
+    }
+
+    let pool = state.db.state();
+    let status = serde_json::json!({
+        "active_connections": state.active_connections.load(Ordering::SeqCst),
+        "total_connections": state.total_connections.load(Ordering::SeqCst),
+        "stream_subscribers": events::subscriber_count(),
+        "read_only": state.read_only.load(Ordering::SeqCst),
+        "pool": {
+            "max_size": state.db.max_size(),
+            "connections": pool.connections,
+            "idle_connections": pool.idle_connections,
+            "in_use": pool.connections - pool.idle_connections,
+        },
+    });
+
+    (OK_RESPONSE.to_string(), status.to_string())
+}
+
+// Handle read-only request function: POST /admin/read-only?enabled=true|false switches read-only mode
+// without a restart; the setting is not persisted, so READ_ONLY applies again on the next start
+fn handle_read_only_request(request: &HttpRequest, state: &AppState) -> (String, String) {
+    if let Err(response) = check_admin_key(request, &state.config) {
+        return response;
+    }
+
+    let enabled = match request.query.get_bool("enabled") {
+        Ok(Some(enabled)) => enabled,
+        Ok(None) => return error_response(BAD_REQUEST, "Query parameter 'enabled' is required"),
+        Err(e) => return error_response(BAD_REQUEST, &e.to_string()),
+    };
+
+    state.read_only.store(enabled, Ordering::SeqCst);
+    log::warn!("Read-only mode {}", if enabled { "enabled" } else { "disabled" });
+    (OK_RESPONSE.to_string(), serde_json::json!({ "read_only": enabled }).to_string())
+}
+
+// Handle debug echo function: the request as the server parsed it, so a client can check what
+// actually arrived. Header names are lowercased, repeated headers and query keys keep every value,
+// and the Authorization value is masked
+fn handle_debug_echo(request: &HttpRequest, config: &Config) -> (String, String) {
+    if !config.debug_endpoints {
+        return error_response(NOT_FOUND, "404 Not Found");
+    }
+
+    let query: BTreeMap<&str, &[String]> = request.query.iter().collect();
+    let mut headers: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for (name, value) in &request.headers {
+        let value = if name.eq_ignore_ascii_case("Authorization") { "[redacted]" } else { value };
+        headers.entry(name.to_lowercase()).or_default().push(value);
+    }
+
+    let echo = serde_json::json!({
+        "method": request.method,
+        "path": request.path,
+        "query": query,
+        "headers": headers,
+        "body": request.body,
+        "body_bytes": request.body.len(),
+    });
+    (OK_RESPONSE.to_string(), echo.to_string())
+}
+
+// Handle list category rules function: every AUTO_CATEGORIZE rule, in the order they are tried
+fn handle_list_category_rules(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    if let Err(response) = check_admin_key(request, config) {
+        return response;
+    }
+
+    match pool.get() {
+        Ok(mut client) => match load_category_rules(&mut client) {
+            Ok(rules) => (OK_RESPONSE.to_string(), serde_json::to_string(&rules).unwrap()),
+            Err(e) => {
+                log::error!("Error fetching category rules: {}", e);
+                db_error_response(&e, "Error fetching category rules")
+            }
+        },
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle create category rule function: body is { "keyword", "category" }; keywords are matched
+// case-insensitively, so they are stored lowercased and must be unique
+fn handle_create_category_rule(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    if let Err(response) = check_admin_key(request, config) {
+        return response;
+    }
+
+    let mut rule = match serde_json::from_str::<CategoryRule>(request.body) {
+        Ok(rule) => rule,
+        Err(e) => return error_response(BAD_REQUEST, &format!("Invalid request body: {}", e)),
+    };
+    rule.keyword = rule.keyword.trim().to_lowercase();
+    rule.category = rule.category.trim().to_string();
+    if rule.keyword.is_empty() || rule.category.is_empty() {
+        return error_response(BAD_REQUEST, "keyword and category must not be empty");
+    }
+
+    match pool.get() {
+        Ok(mut client) => match client.query_one(
+            "INSERT INTO category_rules (keyword, category) VALUES ($1, $2) RETURNING id",
+            &[&rule.keyword, &rule.category],
+        ) {
+            Ok(row) => {
+                rule.id = row.get(0);
+                let location = format!("/admin/category-rules/{}", rule.id.unwrap_or_default());
+                (with_header(CREATED_RESPONSE, "Location", &location), serde_json::to_string(&rule).unwrap())
+            }
+            Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+                error_response(CONFLICT, &format!("A rule for '{}' already exists", rule.keyword))
+            }
+            Err(e) => {
+                log::error!("Error inserting category rule: {}", e);
+                db_error_response(&e, "Error inserting category rule")
+            }
+        },
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle delete category rule function
+fn handle_delete_category_rule(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    if let Err(response) = check_admin_key(request, config) {
+        return response;
+    }
+
+    let id = match request.segment(2).parse::<i32>() {
+        Ok(id) => id,
+        Err(_) => return error_response(BAD_REQUEST, "Invalid rule id"),
+    };
+
+    match pool.get() {
+        Ok(mut client) => match client.execute("DELETE FROM category_rules WHERE id = $1", &[&id]) {
+            Ok(0) => error_response(NOT_FOUND, "Category rule not found"),
+            Ok(_) => (NO_CONTENT.to_string(), String::new()),
+            Err(e) => {
+                log::error!("Error deleting category rule: {}", e);
+                db_error_response(&e, "Error deleting category rule")
+            }
+        },
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            error_response(INTERNAL_SERVER_ERROR, "Database connection error")
+        }
+    }
+}
+
+// Handle apply category rules function: backfills the category of existing uncategorized articles
+// from the current rules, whether or not AUTO_CATEGORIZE is on, and answers with how many changed
+fn handle_apply_category_rules(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    if let Err(response) = check_admin_key(request, config) {
+        return response;
+    }
+
+    let mut client = match pool.get() {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            return error_response(INTERNAL_SERVER_ERROR, "Database connection error");
+        }
+    };
+
+    let backfill = |client: &mut Connection| -> Result<u64, PostgresError> {
+        let mut transaction = client.transaction()?;
+        let rules = load_category_rules(&mut transaction)?;
+        let rows = transaction.query("SELECT id, title, content FROM articles WHERE category IS NULL", &[])?;
+
+        let mut updated = 0;
+        for row in &rows {
+            let content = decrypt_content(config, row.get(2));
+            if let Some(category) = match_category(&rules, row.get(1), &content) {
+                let id: i32 = row.get(0);
+                updated += transaction.execute("UPDATE articles SET category = $1, updated_at = now() WHERE id = $2", &[&category, &id])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(updated)
+    };
+
+    match backfill(&mut client) {
+        Ok(updated) => {
+            log::info!("Categorized {} existing articles", updated);
+            (OK_RESPONSE.to_string(), serde_json::json!({ "updated": updated }).to_string())
+        }
+        Err(e) => {
+            log::error!("Error applying category rules: {}", e);
+            db_error_response(&e, "Error applying category rules")
+        }
+    }
+}
+
+// Handle export backup function: writes every article as JSON to a file under BACKUP_DIR, or with
+// ?async=true starts a background export and answers 202 with the URL to poll for the file
+fn handle_export_backup(request: &HttpRequest, state: &Arc<AppState>) -> (String, String) {
+    let config = &state.config;
+    let pool = &state.db;
+    if let Err(response) = check_admin_key(request, config) {
+        return response;
+    }
+    let slot = match state.export_slots.try_claim() {
+        Some(slot) => slot,
+        None => return state.export_slots.busy_response("exports"),
+    };
+
+    match request.query.get_flag("async") {
+        Ok(true) if config.async_exports => return start_export_job(state, slot),
+        Ok(_) => {}
+        Err(e) => return error_response(BAD_REQUEST, &e.to_string()),
+    }
+
+    let backup_dir = match &config.backup_dir {
+        Some(dir) => dir,
+        None => return error_response(NOT_FOUND, "Backups are disabled; set BACKUP_DIR to enable them"),
+    };
+
+    let body = request.body;
+    let export = match serde_json::from_str::<ExportBackupRequest>(body) {
+        Ok(export) => export,
+        Err(e) => return error_response(BAD_REQUEST, &format!("Invalid request body: {}", e)),
+    };
+
+    // Only plain relative paths are accepted, so the file always lands inside BACKUP_DIR
+    let relative = Path::new(&export.path);
+    if export.path.is_empty() || !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return error_response(BAD_REQUEST, "path must be a relative path inside the backup directory");
+    }
+    let path = backup_dir.join(relative);
+
+    let articles = match fetch_all_articles(config, pool) {
+        Ok(articles) => articles,
+        Err(response) => return response,
+    };
+
+    let json = serde_json::to_string(&articles).unwrap();
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, &json));
+    if let Err(e) = written {
+        log::error!("Error writing backup to {}: {}", path.display(), e);
+        return error_response(INTERNAL_SERVER_ERROR, "Error writing backup file");
+    }
+
+    log::info!("Wrote backup of {} articles to {}", articles.len(), path.display());
+    (OK_RESPONSE.to_string(), serde_json::json!({ "path": path.display().to_string(), "bytes": json.len() }).to_string())
+}
+
+// Every article, decrypted, in id order
+fn fetch_all_articles(config: &Config, pool: &DbPool) -> Result<Vec<Article>, (String, String)> {
+    match pool.get() {
+        Ok(mut client) => match client.query("SELECT * FROM articles ORDER BY id", &[]) {
+            Ok(rows) => Ok(rows.iter().map(|row| row_to_article(row, config)).collect()),
+            Err(e) => {
+                log::error!("Error fetching articles for export: {}", e);
+                Err(error_response(INTERNAL_SERVER_ERROR, "Error fetching articles"))
+            }
+        },
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            Err(error_response(INTERNAL_SERVER_ERROR, "Database connection error"))
+        }
+    }
+}
+
+// Start a background export of every article into the temp dir and answer 202 with its poll URL;
+// the export keeps its slot until the file is written
+fn start_export_job(state: &Arc<AppState>, slot: RouteSlot) -> (String, String) {
+    remove_expired_exports(state);
+
+    let id = state.next_export_id.fetch_add(1, Ordering::SeqCst);
+    state.export_jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(id, ExportJob { started: Instant::now(), status: ExportStatus::Running });
+
+    let state = Arc::clone(state);
+    thread::spawn(move || {
+        let _slot = slot;
+        let path = env::temp_dir().join("newsapi-exports").join(format!("{}.json", id));
+        let written = fetch_all_articles(&state.config, &state.db)
+            .map_err(|(_, message)| message)
+            .and_then(|articles| {
+                let json = serde_json::to_string(&articles).unwrap();
+                path.parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| fs::write(&path, json))
+                    .map_err(|e| e.to_string())
+            });
+
+        let status = match written {
+            Ok(()) => {
+                log::info!("Export {} written to {}", id, path.display());
+                ExportStatus::Ready(path)
+            }
+            Err(e) => {
+                log::error!("Export {} failed: {}", id, e);
+                ExportStatus::Failed
+            }
+        };
+        let mut jobs = state.export_jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(job) = jobs.get_mut(&id) {
+            job.status = status;
+        }
+    });
+
+    let poll_url = format!("/exports/{}", id);
+    (
+        with_header(ACCEPTED, "Location", &poll_url),
+        serde_json::json!({ "id": id, "status": "running", "poll_url": poll_url }).to_string(),
+    )
+}
+
+// Handle export download function: 202 while the job runs, then the exported file until EXPORT_TTL_SECS passes
+fn handle_export_download(request: &HttpRequest, state: &AppState) -> (String, String) {
+    if let Err(response) = check_admin_key(request, &state.config) {
+        return response;
+    }
+    remove_expired_exports(state);
+
+    let id = match request.segment(1).parse::<u64>() {
+        Ok(id) => id,
+        Err(_) => return error_response(BAD_REQUEST, "Invalid export id"),
+    };
+
+    let jobs = state.export_jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match jobs.get(&id).map(|job| &job.status) {
+        Some(ExportStatus::Running) => (
+            with_header(ACCEPTED, "Retry-After", "1"),
+            serde_json::json!({ "id": id, "status": "running", "poll_url": format!("/exports/{}", id) }).to_string(),
+        ),
+        Some(ExportStatus::Ready(path)) => match fs::read_to_string(path) {
+            Ok(json) => (with_header(OK_RESPONSE, "Content-Disposition", &format!("attachment; filename=\"export-{}.json\"", id)), json),
+            Err(e) => {
+                log::error!("Error reading export {}: {}", path.display(), e);
+                error_response(INTERNAL_SERVER_ERROR, "Error reading export file")
+            }
+        },
+        Some(ExportStatus::Failed) => error_response(INTERNAL_SERVER_ERROR, "Export failed"),
+        None => error_response(NOT_FOUND, "Export not found or expired"),
+    }
+}
+
+// Forget exports older than EXPORT_TTL_SECS and delete their files
+fn remove_expired_exports(state: &AppState) {
+    let ttl = Duration::from_secs(state.config.export_ttl_secs);
+    let mut jobs = state.export_jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    jobs.retain(|id, job| {
+        if job.started.elapsed() < ttl {
+            return true;
+        }
+        if let ExportStatus::Ready(path) = &job.status {
+            if let Err(e) = fs::remove_file(path) {
+                log::warn!("Could not remove expired export {}: {}", id, e);
+            }
+        }
+        false
+    });
+}
+
+// Handle purge by query function: deletes the articles matching every given filter
+fn handle_purge_by_query(request: &HttpRequest, config: &Config, pool: &DbPool) -> (String, String) {
+    if let Err(response) = check_admin_key(request, config) {
+        return response;
+    }
+
+    let body = request.body;
+    let purge = match serde_json::from_str::<PurgeRequest>(body) {
+        Ok(purge) => purge,
+        Err(e) => return error_response(BAD_REQUEST, &format!("Invalid request body: {}", e)),
+    };
+    if !purge.confirm {
+        return error_response(BAD_REQUEST, "Purging requires \"confirm\": true");
+    }
+
+    let source = purge.source.as_deref().map(|source| {
+        if config.normalize_source { normalize_source(source) } else { source.to_string() }
+    });
+    let mut conditions = Vec::new();
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+    if let Some(source) = &source {
+        params.push(source);
+        conditions.push(format!("source = ${}", params.len()));
+    }
+    if let Some(created_before) = &purge.created_before {
+        params.push(created_before);
+        conditions.push(format!("created_at < ${}", params.len()));
+    }
+    if let Some(title_like) = &purge.title_like {
+        params.push(title_like);
+        conditions.push(format!("title LIKE ${}", params.len()));
+    }
+    // An empty filter would delete every article
+    if conditions.is_empty() {
+        return error_response(BAD_REQUEST, "At least one of source, created_before or title_like is required");
+    }
+
+    let mut client = match pool.get() {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("Database connection error: {}", e);
+            return error_response(INTERNAL_SERVER_ERROR, "Database connection error");
+        }
+    };
+    let query = format!("DELETE FROM articles WHERE {}", conditions.join(" AND "));
+    let purged = client.transaction().and_then(|mut transaction| {
+        let purged = transaction.execute(&query, &params)?;
+        transaction.commit()?;
+        Ok(purged)
+    });
+
+    match purged {
+        Ok(purged) => {
+            log::info!("Purged {} articles", purged);
+            (OK_RESPONSE.to_string(), serde_json::json!({ "purged": purged }).to_string())
+        }
+        Err(e) => {
+            log::error!("Error purging articles: {}", e);
+            db_error_response(&e, "Error purging articles")
+        }
+    }
+}
+
+// Require "Authorization: Bearer <API_KEY>" on admin routes; they are refused outright when API_KEY is unset
+fn check_admin_key(request: &HttpRequest, config: &Config) -> Result<(), (String, String)> {
+    let key = match &config.api_key {
+        Some(key) => key,
+        None => return Err(error_response(FORBIDDEN, "Admin endpoints require API_KEY to be configured")),
+    };
+
+    if is_authorized(request, key) {
+        Ok(())
+    } else {
+        Err(error_response(UNAUTHORIZED, "Missing or invalid API key"))
+    }
+}
+
+// Check the request's "Authorization: Bearer <key>" header against the expected key
+fn is_authorized(request: &HttpRequest, expected: &str) -> bool {
+    request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token.trim() == expected)
+}
+
+// Whether the request uses a method that changes data (POST, PUT, PATCH or DELETE), leaving out the
+// POST routes that only read: batch lookups, selector tests and the echo endpoint
+fn is_write_request(request: &HttpRequest) -> bool {
+    match (request.method, request.segments.as_slice()) {
+        ("POST", ["articles", "batch-get"] | ["scrape", "test"] | ["debug", "echo"]) => false,
+        (method, _) => matches!(method, "POST" | "PUT" | "PATCH" | "DELETE"),
+    }
+}
+
+// Build an error response with a JSON body like {"error": "..."} so clients can parse every response as JSON
+fn error_response(status_line: &str, message: &str) -> (String, String) {
+    (
+        with_header(status_line, "Content-Type", "application/json"),
+        serde_json::json!({ "error": message }).to_string(),
+    )
+}
+
+// Build a 200 response with a JSON body like {"message": "..."} for writes that return no resource
+fn message_response(message: &str) -> (String, String) {
+    (OK_RESPONSE.to_string(), serde_json::json!({ "message": message }).to_string())
+}
+
+// Whether the Accept header weights text/plain above application/json (on equal q-values the first listed wins)
+fn prefers_plain_text(request: &HttpRequest) -> bool {
+    let mut preferred: Option<(&str, f32)> = None;
+    for (media_type, q) in request.header("Accept").unwrap_or_default().split(',').map(parse_accept_item) {
+        let known = media_type == "text/plain" || media_type == "application/json";
+        if known && q > 0.0 && preferred.is_none_or(|(_, best)| q > best) {
+            preferred = Some((media_type, q));
+        }
+    }
+    preferred.map(|(media_type, _)| media_type) == Some("text/plain")
+}
+
+// Turn an error response built by error_response into a plain-text line; other responses are left alone
+fn to_plain_text_error(status_line: String, content: String) -> (String, String) {
+    let json_type = "\r\nContent-Type: application/json\r\n";
+    let message = serde_json::from_str::<serde_json::Value>(&content)
+        .ok()
+        .and_then(|body| body.get("error").and_then(|error| error.as_str()).map(str::to_string));
+
+    match message {
+        Some(message) if status_line.contains(json_type) && !status_line.starts_with("HTTP/1.1 2") => {
+            (status_line.replacen(json_type, "\r\nContent-Type: text/plain\r\n", 1), format!("{}\n", message))
+        }
+        _ => (status_line, content),
+    }
+}
+
+// Map a database error on a write path to a response: 503 while the articles table is missing
+// (e.g. migrations still running at startup), 409 on a duplicate, otherwise a 500 with the given message
+fn db_error_response(e: &PostgresError, message: &str) -> (String, String) {
+    if e.code() == Some(&SqlState::UNDEFINED_TABLE) {
+        return error_response(SERVICE_UNAVAILABLE, "Schema not ready");
+    }
+    if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+        return error_response(CONFLICT, "Article already exists");
+    }
+
+    error_response(INTERNAL_SERVER_ERROR, message)
+}
+
+// Announce a newly created article to /articles/stream subscribers and WEBHOOK_URL
+fn announce_article(config: &Config, article: &Article, request_id: Option<&str>) {
+    announce_articles(config, std::slice::from_ref(article), request_id);
+}
+
+// Announce the articles created by one insert, scrape or bulk insert. Each goes to the stream subscribers;
+// WEBHOOK_URL gets one call per article, or with WEBHOOK_BATCH a single call carrying a JSON array of them.
+// Articles loaded through COPY have no generated id to report, so only inserted ones are announced
+fn announce_articles(config: &Config, articles: &[Article], request_id: Option<&str>) {
+    let articles: Vec<&Article> = articles.iter().filter(|article| article.id.is_some()).collect();
+    for article in &articles {
+        events::publish(ArticleEvent { id: article.id.unwrap_or_default(), data: article_payload(article).to_string() });
+    }
+    if config.webhook_url.is_none() || articles.is_empty() {
+        return;
+    }
+
+    if config.webhook_batch {
+        let payload: Vec<serde_json::Value> = articles.iter().map(|article| article_payload(article)).collect();
+        send_webhook(config, serde_json::Value::Array(payload).to_string(), request_id);
+    } else {
+        for article in articles {
+            send_webhook(config, article_payload(article).to_string(), request_id);
+        }
+    }
+}
+
+// An article as announced to listeners, without its raw HTML
+fn article_payload(article: &Article) -> serde_json::Value {
+    let mut payload = serde_json::to_value(article).unwrap();
+    if let Some(fields) = payload.as_object_mut() {
+        fields.remove("raw_html");
+    }
+    payload
+}
+
+// POST a JSON body to WEBHOOK_URL on a background thread; failures are only logged
+fn send_webhook(config: &Config, body: String, request_id: Option<&str>) {
+    let url = match &config.webhook_url {
+        Some(url) => url.clone(),
+        None => return,
+    };
+    let request_id = request_id.map(str::to_string);
+
+    thread::spawn(move || {
+        let result = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .and_then(|client| {
+                with_request_id(client.post(&url), request_id.as_deref())
+                    .header("Content-Type", "application/json")
+                    .body(body)
+                    .send()
+            })
+            .and_then(|res| res.error_for_status());
+
+        if let Err(e) = result {
+            log::error!("Error calling webhook: {}", e);
+        }
+    });
+}
+
+// Forward the incoming X-Request-Id on an outbound request so calls can be traced end-to-end
+fn with_request_id(builder: reqwest::blocking::RequestBuilder, request_id: Option<&str>) -> reqwest::blocking::RequestBuilder {
+    match request_id {
+        Some(request_id) => builder.header("X-Request-Id", request_id),
+        None => builder,
+    }
+}
+
+// With AUTO_CATEGORIZE, articles arriving without a category get the category of the first rule
+// (in the order the rules were added) whose keyword appears in their title or content
+fn apply_category_rules(client: &mut impl Queries, config: &Config, articles: &mut [Article]) -> Result<(), PostgresError> {
+    if !config.auto_categorize || articles.iter().all(|article| article.category.is_some()) {
+        return Ok(());
+    }
+
+    let rules = load_category_rules(client)?;
+    for article in articles.iter_mut().filter(|article| article.category.is_none()) {
+        article.category = match_category(&rules, &article.title, &article.content);
+    }
+    Ok(())
+}
+
+fn load_category_rules(client: &mut impl Queries) -> Result<Vec<CategoryRule>, PostgresError> {
+    let rows = client.query("SELECT id, keyword, category FROM category_rules ORDER BY id", &[])?;
+    Ok(rows.iter().map(|row| CategoryRule { id: row.get(0), keyword: row.get(1), category: row.get(2) }).collect())
+}
+
+// The category of the first rule whose keyword appears as a whole word (or phrase) in the title or content
+fn match_category(rules: &[CategoryRule], title: &str, content: &str) -> Option<String> {
+    let text = format!("{}\n{}", title, content).to_lowercase();
+    rules.iter().find(|rule| contains_word(&text, &rule.keyword.to_lowercase())).map(|rule| rule.category.clone())
+}
+
+// Whether keyword occurs in text with no letter or digit right before or after it, so "art" doesn't match "party"
+fn contains_word(text: &str, keyword: &str) -> bool {
+    !keyword.is_empty()
+        && text.match_indices(keyword).any(|(start, _)| {
+            let before = text[..start].chars().next_back();
+            let after = text[start + keyword.len()..].chars().next();
+            !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+        })
+}
+
+// MAX_ARTICLES is enforced inside the write transaction: lock_for_article_limit runs before the inserts
+// and trim_to_article_limit after them, once it is known how many rows were actually added.
+// Lock the articles table against other writers until the transaction ends, so concurrent inserts can't
+// both fit under the limit, and return the newest id from before this transaction's inserts; None (and
+// no lock) when there is no limit
+fn lock_for_article_limit(client: &mut impl Queries, config: &Config) -> Result<Option<i32>, PostgresError> {
+    if config.max_articles.is_none() {
+        return Ok(None);
+    }
+
+    client.batch_execute("LOCK TABLE articles IN SHARE ROW EXCLUSIVE MODE")?;
+    let newest: i32 = client.query_one("SELECT COALESCE(MAX(id), 0) FROM articles", &[])?.get(0);
+    Ok(Some(newest))
+}
+
+// After a transaction inserted `inserted` rows: false when the table is now over MAX_ARTICLES and the
+// policy is reject (or the batch alone exceeds the limit), for the caller to roll back; otherwise the
+// oldest rows from before the transaction are evicted until it fits. A write that added nothing never evicts
+fn trim_to_article_limit(client: &mut impl Queries, config: &Config, newest_before: Option<i32>, inserted: i64) -> Result<bool, PostgresError> {
+    let (max_articles, newest_before) = match (config.max_articles, newest_before) {
+        (Some(max), Some(newest)) => (max, newest),
+        _ => return Ok(true),
+    };
+    if inserted == 0 {
+        return Ok(true);
+    }
+
+    let count: i64 = client.query_one("SELECT COUNT(*) FROM articles", &[])?.get(0);
+    let excess = count - max_articles;
+    if excess <= 0 {
+        return Ok(true);
+    }
+
+    if config.cap_policy == CapPolicy::Reject || inserted > max_articles {
+        return Ok(false);
+    }
+
+    client.execute(
+        "DELETE FROM articles WHERE id IN (SELECT id FROM articles WHERE id <= $2 ORDER BY id LIMIT $1)",
+        &[&excess, &newest_before]
+    )?;
+
+    Ok(true)
+}
+
+// Set database function
+// Connect to the database, retrying up to DB_CONNECT_ATTEMPTS times with a delay that doubles from
+// one second, so the server can start before Postgres is ready
+fn connect_with_retry(config: &Config) -> Result<Client, PostgresError> {
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        log::info!("Connecting to database (attempt {}/{})...", attempt, config.db_connect_attempts);
+        match config.db.connect(NoTls) {
+            Ok(client) => return Ok(client),
+            Err(e) if attempt < config.db_connect_attempts => {
+                log::warn!("Database connection failed: {}; retrying in {}s", e, delay.as_secs());
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Tables MIGRATIONS and run_migrations create, with the columns the handlers use; keep in step with them
+const SCHEMA_TABLES: &[(&str, &[&str])] = &[
+    (
+        "articles",
+        &[
+            "id", "title", "content", "source", "raw_html", "position", "image_url", "url", "normalized_url",
+            "lat", "lon", "created_at", "updated_at", "published_at", "category",
+        ],
+    ),
+    ("category_rules", &["id", "keyword", "category"]),
+    ("schema_migrations", &["version", "applied_at"]),
+];
+
+// Check that every table in SCHEMA_TABLES exists with its columns, without changing anything
+fn verify_schema(config: &Config) -> Result<(), String> {
+    let mut client = connect_with_retry(config).map_err(|e| e.to_string())?;
+    for (table, expected) in SCHEMA_TABLES {
+        let rows = client
+            .query(
+                "SELECT column_name::text FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1",
+                &[table],
+            )
+            .map_err(|e| e.to_string())?;
+        if rows.is_empty() {
+            return Err(format!("SKIP_MIGRATIONS is set but the {} table does not exist; run the migrations first", table));
+        }
+
+        let columns: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+        let missing: Vec<&str> = expected.iter().copied().filter(|column| !columns.iter().any(|c| c == column)).collect();
+        if !missing.is_empty() {
+            return Err(format!("SKIP_MIGRATIONS is set but the {} table is missing columns: {}", table, missing.join(", ")));
+        }
+    }
+
+    Ok(())
+}
+
+// Migration: one schema change. A change gated on a config flag waits until the flag is set, and a unique
+// index that existing duplicate rows block logs its warning and is retried on the next start instead
+struct Migration {
+    sql: &'static str,
+    enabled: fn(&Config) -> bool,
+    duplicates_warning: Option<&'static str>,
+}
+
+// A migration every database gets
+const fn change(sql: &'static str) -> Migration {
+    Migration { sql, enabled: |_| true, duplicates_warning: None }
+}
+
+// Schema migrations in the order they apply; version N is the Nth entry. Append new ones at the end
+// and never edit one that has shipped, since databases that ran it won't run it again. The early ones
+// use IF NOT EXISTS so they run over databases created before versioning
+const MIGRATIONS: &[Migration] = &[
+    // 1
+    change(
+        "CREATE TABLE IF NOT EXISTS articles (
+            id SERIAL PRIMARY KEY,
+            title VARCHAR NOT NULL,
+            content TEXT NOT NULL,
+            source VARCHAR NOT NULL
+        )",
+    ),
+    // 2
+    change("ALTER TABLE articles ADD COLUMN IF NOT EXISTS raw_html TEXT"),
+    // 3
+    change(
+        "CREATE SEQUENCE IF NOT EXISTS articles_position_seq;
+        ALTER TABLE articles ADD COLUMN IF NOT EXISTS position INT DEFAULT nextval('articles_position_seq')",
+    ),
+    // 4
+    change("ALTER TABLE articles ADD COLUMN IF NOT EXISTS image_url VARCHAR"),
+    // 5
+    change("ALTER TABLE articles ADD COLUMN IF NOT EXISTS url VARCHAR"),
+    // 6
+    change("ALTER TABLE articles ADD COLUMN IF NOT EXISTS normalized_url VARCHAR"),
+    // 7
+    change(
+        "ALTER TABLE articles ADD COLUMN IF NOT EXISTS lat DOUBLE PRECISION;
+        ALTER TABLE articles ADD COLUMN IF NOT EXISTS lon DOUBLE PRECISION",
+    ),
+    // 8
+    change(
+        "ALTER TABLE articles ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+        ALTER TABLE articles ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT now()",
+    ),
+    // 9
+    change("ALTER TABLE articles ADD COLUMN IF NOT EXISTS published_at TIMESTAMPTZ"),
+    // 10
+    change("ALTER TABLE articles ADD COLUMN IF NOT EXISTS category VARCHAR"),
+    // 11
+    change(
+        "CREATE TABLE IF NOT EXISTS category_rules (
+            id SERIAL PRIMARY KEY,
+            keyword VARCHAR NOT NULL UNIQUE,
+            category VARCHAR NOT NULL
+        )",
+    ),
+    // 12: one row per title and source, so repeated scrapes skip what they already stored
+    Migration {
+        sql: "CREATE UNIQUE INDEX IF NOT EXISTS articles_title_source_key ON articles (title, source)",
+        enabled: |_| true,
+        duplicates_warning: Some("duplicate (title, source) rows exist; remove them to enable scrape deduplication"),
+    },
+    // 13: with DEDUP_CASE_INSENSITIVE, case variants of the same title and source count as duplicates
+    Migration {
+        sql: "CREATE UNIQUE INDEX IF NOT EXISTS articles_title_source_lower_key ON articles (lower(title), lower(source))",
+        enabled: |config| config.dedup_case_insensitive,
+        duplicates_warning: Some("case variants of the same (title, source) exist; remove them to enable DEDUP_CASE_INSENSITIVE"),
+    },
+    // 14: with DEDUP_BY_URL, tracking-parameter variants of the same story URL count as duplicates
+    Migration {
+        sql: "CREATE UNIQUE INDEX IF NOT EXISTS articles_normalized_url_key ON articles (normalized_url) WHERE normalized_url IS NOT NULL",
+        enabled: |config| config.dedup_by_url,
+        duplicates_warning: Some("duplicate normalized URLs exist; remove them to enable DEDUP_BY_URL"),
+    },
+];
+
+// Apply the enabled migrations the database hasn't run yet, all in one transaction, recording each version
+// in schema_migrations; a database that is already current is left untouched
+fn run_migrations(client: &mut Client, config: &Config) -> Result<(), PostgresError> {
+    client.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INT PRIMARY KEY,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )"
+    )?;
+
+    let mut transaction = client.transaction()?;
+    // Instances starting together against one database take turns, so each migration runs once
+    transaction.batch_execute("LOCK TABLE schema_migrations IN EXCLUSIVE MODE")?;
+    let applied: Vec<i32> = transaction.query("SELECT version FROM schema_migrations", &[])?.iter().map(|row| row.get(0)).collect();
+    if let Some(newest) = applied.iter().copied().filter(|version| *version as usize > MIGRATIONS.len()).max() {
+        log::warn!("Database schema is at version {}, newer than this build's {}", newest, MIGRATIONS.len());
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate() {
+        let version = index as i32 + 1;
+        if applied.contains(&version) || !(migration.enabled)(config) {
+            continue;
+        }
+
+        match migration.duplicates_warning {
+            None => transaction.batch_execute(migration.sql)?,
+            // In a savepoint, so a duplicate only undoes this migration and the others still apply
+            Some(warning) => {
+                let mut savepoint = transaction.transaction()?;
+                match savepoint.batch_execute(migration.sql) {
+                    Ok(()) => savepoint.commit()?,
+                    Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+                        log::warn!("{}", warning);
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        transaction.execute("INSERT INTO schema_migrations (version) VALUES ($1)", &[&version])?;
+        log::info!("Applied schema migration {}", version);
+    }
+    transaction.commit()
+}
+
+fn set_database(config: &Config) -> Result<(), PostgresError> {
+    // Connect to database
+    let mut client = connect_with_retry(config)?;
+
+    run_migrations(&mut client, config)
+}
+
+// Whether the query string has more than MAX_QUERY_PARAMS parameters; empty pairs like "a=1&&b=2" don't count
+fn has_too_many_query_params(request: &HttpRequest, config: &Config) -> bool {
+    QueryParams::count(request.query_string) > config.max_query_params
+}
+
+// Detect duplicate/conflicting Content-Length headers or Content-Length combined with
+// Transfer-Encoding, which RFC 7230 section 3.3.3 says must be rejected to avoid request smuggling
+fn has_ambiguous_framing(request: &HttpRequest) -> bool {
+    let content_lengths: Vec<&str> =
+        request.header_values("Content-Length").flat_map(|value| value.split(',')).map(str::trim).collect();
+
+    if content_lengths.is_empty() {
+        return false;
+    }
+
+    request.header("Transfer-Encoding").is_some()
+        || content_lengths.iter().any(|v| v.parse::<usize>().is_err())
+        || content_lengths.iter().any(|v| v != &content_lengths[0])
+}
+
+// Add Deprecation, Sunset (when LEGACY_SUNSET is set) and successor Link headers to a legacy route's
+// response and log its use; DEPRECATION_HEADERS=false only keeps the log line
+fn mark_deprecated(response: (String, String), config: &Config, request: &HttpRequest, successor: &str) -> (String, String) {
+    log::warn!("Deprecated route used: {} {} (use {} instead)", request.method, request.target, successor);
+    if !config.deprecation_headers {
+        return response;
+    }
+
+    let (status_line, content) = response;
+    let status_line = with_header(&status_line, "Deprecation", "true");
+    let status_line = match &config.legacy_sunset {
+        Some(sunset) => with_header(&status_line, "Sunset", sunset),
+        None => status_line,
+    };
+    (with_header(&status_line, "Link", &format!("<{}>; rel=\"successor-version\"", successor)), content)
+}
+
+// Methods the router accepts on a path, or None when no route has that path
+fn get_allowed_methods(segments: &[&str]) -> Option<&'static str> {
+    match segments {
+        ["articles"] => Some("GET, POST, DELETE, OPTIONS"),
+        ["articles", "bulk" | "batch-get" | "import-from-url"] => Some("POST, OPTIONS"),
+        ["articles", "schema.json" | "sample" | "search" | "stream"] => Some("GET, OPTIONS"),
+        ["articles", _] => Some("GET, PUT, PATCH, DELETE, OPTIONS"),
+        ["articles", "source", _] => Some("DELETE, OPTIONS"),
+        ["articles", _, "exists" | "image"] | ["articles", "since", _] => Some("GET, OPTIONS"),
+        ["articles", _, "promote" | "demote"] | ["articles", _, "move", _] => Some("POST, OPTIONS"),
+        ["scrape", "source", source] if find_scraper(source).is_some() => Some("DELETE, OPTIONS"),
+        ["scrape", _] | ["scrape", _, "refresh"] => Some("POST, OPTIONS"),
+        ["admin", "export-backup" | "purge-by-query" | "read-only"] | ["admin", "category-rules", "apply"] => Some("POST, OPTIONS"),
+        ["admin", "category-rules"] => Some("GET, POST, OPTIONS"),
+        ["debug", "echo"] => Some("POST, OPTIONS"),
+        ["admin", "category-rules", _] => Some("DELETE, OPTIONS"),
+        ["ping"] | ["version"] | ["favicon.ico"] | ["articles.geojson"] | ["articles.rss"] | ["feed", _] | ["oembed"] | ["admin", "status"] | ["exports", _] => {
+            Some("GET, OPTIONS")
+        }
+        _ => None,
+    }
+}
+
+// Build the Link header value with rel="next"/"prev" page URLs for a list response, empty when there is one page
+fn get_pagination_links(request: &HttpRequest, limit: i64, offset: i64, total: i64) -> String {
+    let mut links = Vec::new();
+    if offset + limit < total {
+        links.push(format!("<{}>; rel=\"next\"", get_page_url(request, &[("limit", limit), ("offset", offset + limit)])));
+    }
+    if offset > 0 {
+        links.push(format!("<{}>; rel=\"prev\"", get_page_url(request, &[("limit", limit), ("offset", (offset - limit).max(0))])));
+    }
+    links.join(", ")
+}
+
+// The request's URL with the given parameters (limit and offset or after) replaced, keeping the others as sent
+fn get_page_url(request: &HttpRequest, replacements: &[(&str, i64)]) -> String {
+    let mut pairs: Vec<String> = request
+        .query_string
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            replacements.iter().all(|(name, _)| *name != key)
+        })
+        .map(str::to_string)
+        .collect();
+    pairs.extend(replacements.iter().map(|(name, value)| format!("{}={}", name, value)));
+
+    format!("{}?{}", request.path, pairs.join("&"))
+}
+
+// Add the CORS headers that let a browser frontend on CORS_ALLOW_ORIGIN call the API
+fn with_cors_headers(status_line: &str, config: &Config) -> String {
+    let status_line = with_header(status_line, "Access-Control-Allow-Origin", &config.cors_allow_origin);
+    let status_line = with_header(&status_line, "Access-Control-Allow-Methods", CORS_ALLOW_METHODS);
+    with_header(&status_line, "Access-Control-Allow-Headers", CORS_ALLOW_HEADERS)
+}
+
+// Add a header to a status line constant, keeping the blank line that ends the head
+fn with_header(status_line: &str, name: &str, value: &str) -> String {
+    let head = status_line.strip_suffix("\r\n\r\n").unwrap_or(status_line);
+    format!("{}\r\n{}: {}\r\n\r\n", head, name, value)
+}
+
+// Get the canonical form of the request target (lowercase path, no repeated or trailing slashes, query
+// kept), or None when the request already uses it
+fn get_canonical_location(request: &HttpRequest) -> Option<String> {
+    let path = request.path;
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let canonical = format!("/{}", segments.join("/")).to_lowercase();
+    if canonical == path {
+        return None;
+    }
+
+    match request.target.split_once('?') {
+        Some((_, query)) => Some(format!("{}?{}", canonical, query)),
+        None => Some(canonical),
+    }
+}
+
+// Escape LIKE wildcards so % and _ in user input match literally (backslash is the default escape)
+fn escape_like(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// Truncate a string to at most max_bytes without splitting a UTF-8 character
+fn truncate_to_char_boundary(value: &str, max_bytes: usize) -> &str {
+    if value.len() <= max_bytes {
+        return value;
+    }
+
+    let mut end = max_bytes;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+// Normalize source function
+fn normalize_source(source: &str) -> String {
+    source.trim().to_lowercase()
+}
+
+// The source named by a path segment, matching how it was stored
+fn source_from_path(config: &Config, segment: &str) -> String {
+    let source = query::url_decode(segment);
+    if config.normalize_source {
+        normalize_source(&source)
+    } else {
+        source
+    }
+}
+
+// Parse LEGACY_SUNSET (YYYY-MM-DD or an RFC 2822 date) into the HTTP-date the Sunset header needs
+fn parse_sunset(value: &str) -> Option<String> {
+    let value = value.trim();
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_time(NaiveTime::MIN).and_utc())
+        .or_else(|_| DateTime::parse_from_rfc2822(value).map(|date| date.with_timezone(&Utc)));
+
+    match date {
+        Ok(date) => Some(date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()),
+        Err(e) => {
+            log::warn!("Ignoring invalid LEGACY_SUNSET {:?}: {}", value, e);
+            None
+        }
+    }
+}
+
+// Read a boolean flag from the environment, falling back to the default when unset or unrecognized
+fn env_flag(var: EnvLookup, name: &str, default: bool) -> bool {
+    match var(name).map(|v| v.trim().to_lowercase()) {
+        Ok(v) if ["1", "true", "yes", "on"].contains(&v.as_str()) => true,
+        Ok(v) if ["0", "false", "no", "off"].contains(&v.as_str()) => false,
+        _ => default,
+    }
+}
+
+// Canonical form of an article URL used for dedup: lowercase scheme and host, no fragment, no trailing
+// slash on the path and no URL_TRACKING_PARAMS query parameters ("utm_*" matches by prefix); None for
+// missing or unparseable URLs
+fn normalize_url(config: &Config, url: Option<&str>) -> Option<String> {
+    let mut url = reqwest::Url::parse(url?.trim()).ok()?;
+    url.set_fragment(None);
+    if url.path().len() > 1 && url.path().ends_with('/') {
+        let path = url.path().trim_end_matches('/').to_string();
+        url.set_path(&path);
+    }
+
+    let is_tracking = |key: &str| {
+        let key = key.to_lowercase();
+        config.url_tracking_params.iter().any(|param| match param.strip_suffix('*') {
+            Some(prefix) => key.starts_with(prefix),
+            None => key == *param,
+        })
+    };
+    let kept: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| !is_tracking(key))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+
+    if kept.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(kept);
+    }
+    Some(url.to_string())
+}
+
+// Check that a URL is http(s) and its host is in SCRAPE_ALLOWED_HOSTS
+fn is_allowed_url(url: &str, config: &Config) -> bool {
+    match reqwest::Url::parse(url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url
+            .host_str()
+            .map(|host| config.scrape_allowed_hosts.iter().any(|allowed| allowed == &host.to_lowercase()))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+// HTTP client for user-supplied URLs that refuses to follow redirects off the allowlist
+fn guarded_http_client(config: &Config) -> reqwest::Result<reqwest::blocking::Client> {
+    let allowed_hosts = config.scrape_allowed_hosts.clone();
+
+    reqwest::blocking::Client::builder()
+        .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+            let allowed = attempt
+                .url()
+                .host_str()
+                .map(|host| allowed_hosts.iter().any(|allowed| allowed == &host.to_lowercase()))
+                .unwrap_or(false);
+
+            if allowed && attempt.previous().len() < 10 { attempt.follow() } else { attempt.stop() }
+        }))
+        .build()
+}
+
+// Build an article for a GET response according to the view options
+fn view_article(row: &Row, config: &Config, view: &ViewOptions) -> Article {
+    let mut article = row_to_article(row, config);
+    if view.include_raw {
+        article.raw_html = row.get("raw_html");
+    }
+    if view.normalize_ws {
+        article.content = article.content.split_whitespace().collect::<Vec<_>>().join(" ");
+    }
+    article
+}
+
+// Serialize a response body, dropping null object fields when omit_nulls is set
+fn render_json<T: Serialize>(value: &T, view: &ViewOptions) -> String {
+    let mut json = serde_json::to_value(value).unwrap();
+    if view.omit_nulls {
+        strip_nulls(&mut json);
+    }
+    json.to_string()
+}
+
+// Recursively remove null-valued fields from JSON objects
+fn strip_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            fields.retain(|_, field| !field.is_null());
+            fields.values_mut().for_each(strip_nulls);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+// Map a full articles row to an Article
+fn row_to_article(row: &Row, config: &Config) -> Article {
+    Article {
+        id: row.get(0),
+        title: row.get(1),
+        content: decrypt_content(config, row.get(2)),
+        source: row.get(3),
+        raw_html: None,
+        position: row.get("position"),
+        image_url: row.get("image_url"),
+        url: row.get("url"),
+        lat: row.get("lat"),
+        lon: row.get("lon"),
+        category: row.get("category"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+        published_at: row.get("published_at"),
+    }
+}
+
+// Prefix marking content encrypted at rest; the format is the prefix followed by base64(nonce || ciphertext)
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+// Prefix marking gzip-compressed content; the format is the prefix followed by base64(gzip bytes)
+const COMPRESSED_PREFIX: &str = "gz:v1:";
+
+// Encode content for storage: compressed when COMPRESS_CONTENT is set, then encrypted when
+// ENCRYPTION_KEY is set, otherwise stored as plaintext
+fn encrypt_content(config: &Config, content: &str) -> String {
+    let content = &compress_content(config, content);
+    let cipher = match &config.content_cipher {
+        Some(cipher) => cipher,
+        None => return content.to_string(),
+    };
+
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, content.as_bytes()).expect("AES-GCM encryption failed");
+
+    let mut payload = nonce.to_vec();
+    payload.extend_from_slice(&ciphertext);
+    format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(payload))
+}
+
+// Decrypt and decompress stored content, passing plaintext rows through unchanged
+fn decrypt_content(config: &Config, stored: String) -> String {
+    let (cipher, encoded) = match (&config.content_cipher, stored.strip_prefix(ENCRYPTED_PREFIX)) {
+        (Some(cipher), Some(encoded)) => (cipher, encoded),
+        _ => return decompress_content(stored),
+    };
+
+    let decrypted = BASE64
+        .decode(encoded)
+        .ok()
+        .filter(|payload| payload.len() > 12)
+        .and_then(|payload| cipher.decrypt(Nonce::from_slice(&payload[..12]), &payload[12..]).ok())
+        .and_then(|plaintext| String::from_utf8(plaintext).ok());
+
+    match decrypted {
+        Some(content) => decompress_content(content),
+        None => {
+            log::error!("Error decrypting article content");
+            stored
+        }
+    }
+}
+
+// Gzip content when COMPRESS_CONTENT is set, keeping the plaintext when compressing doesn't make it smaller
+fn compress_content(config: &Config, content: &str) -> String {
+    if !config.compress_content {
+        return content.to_string();
+    }
+
+    let compressed = gzip(content.as_bytes()).map(|bytes| format!("{}{}", COMPRESSED_PREFIX, BASE64.encode(bytes)));
+
+    match compressed {
+        Ok(compressed) if compressed.len() < content.len() => compressed,
+        _ => content.to_string(),
+    }
+}
+
+// Decompress content stored by compress_content; rows without the prefix are returned as they are,
+// whatever COMPRESS_CONTENT is set to now
+fn decompress_content(stored: String) -> String {
+    let encoded = match stored.strip_prefix(COMPRESSED_PREFIX) {
+        Some(encoded) => encoded,
+        None => return stored,
+    };
+
+    let mut content = String::new();
+    let decompressed = BASE64
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| GzDecoder::new(&bytes[..]).read_to_string(&mut content).ok());
+
+    match decompressed {
+        Some(_) => content,
+        None => {
+            log::error!("Error decompressing article content");
+            stored
+        }
+    }
+}
+
+// Deserialize article from request body
+fn get_article_request_body(request: &HttpRequest) -> Result<Article, serde_json::Error> {
+    serde_json::from_str(request.body)
+}
+
+// JSON Schema of the article body accepted by POST and PUT, describing the checks in validate_article
+fn article_schema(config: &Config) -> serde_json::Value {
+    serde_json::json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Article",
+        "type": "object",
+        "required": ["title", "content", "source"],
+        "properties": {
+            "title": { "type": "string", "minLength": 1, "maxLength": config.title_max_len, "pattern": "\\S" },
+            "content": { "type": "string", "maxLength": config.content_max_len },
+            "source": if config.allowed_sources.is_empty() {
+                serde_json::json!({ "type": "string", "minLength": 1, "pattern": "\\S" })
+            } else {
+                serde_json::json!({ "type": "string", "enum": config.allowed_sources })
+            },
+            "url": { "type": ["string", "null"] },
+            "image_url": { "type": ["string", "null"] },
+            "lat": { "type": ["number", "null"], "minimum": -90, "maximum": 90 },
+            "lon": { "type": ["number", "null"], "minimum": -180, "maximum": 180 },
+            "category": { "type": ["string", "null"] }
+        }
+    })
+}
+
+// Deserialize a JSON array of articles from request body
+fn get_articles_request_body(request: &HttpRequest) -> Result<Vec<Article>, serde_json::Error> {
+    serde_json::from_str(request.body)
+}
+
+// Deserialize and validate the article in a POST/PUT body, describing the problem on failure
+fn get_valid_article(request: &HttpRequest, config: &Config) -> Result<Article, String> {
+    let article = get_article_request_body(request).map_err(|e| format!("Invalid article body: {}", e))?;
+    validate_article(&article, config)?;
+    Ok(article)
+}
+
+// Check that title and source are not empty or whitespace-only, source is in ALLOWED_SOURCES when set,
+// title and content are within the configured lengths and coordinates are in range; keep article_schema
+// in step with these rules
+fn validate_article(article: &Article, config: &Config) -> Result<(), String> {
+    if article.title.trim().is_empty() {
+        return Err("title must not be empty".to_string());
+    }
+    if article.title.chars().count() > config.title_max_len {
+        return Err(format!("title must be at most {} characters", config.title_max_len));
+    }
+    if article.content.chars().count() > config.content_max_len {
+        return Err(format!("content must be at most {} characters", config.content_max_len));
+    }
+    if article.source.trim().is_empty() {
+        return Err("source must not be empty".to_string());
+    }
+    if !config.allowed_sources.is_empty() && !config.allowed_sources.contains(&normalize_source(&article.source)) {
+        return Err(format!("source must be one of: {}", config.allowed_sources.join(", ")));
+    }
+    if article.lat.is_some_and(|lat| !(-90.0..=90.0).contains(&lat)) {
+        return Err("lat must be between -90 and 90".to_string());
+    }
+    if article.lon.is_some_and(|lon| !(-180.0..=180.0).contains(&lon)) {
+        return Err("lon must be between -180 and 180".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(raw: &str) -> HttpRequest<'_> {
+        HttpRequest::parse(raw).unwrap()
+    }
+
+    // Default config; tests set the fields they need
+    fn test_config() -> Config {
+        Config::for_tests()
+    }
+
+    fn config_with_key() -> Config {
+        let mut config = test_config();
+        config.content_cipher = Some(Aes256Gcm::new_from_slice(&[7; 32]).unwrap());
+        config
+    }
+
+    #[test]
+    fn single_content_length_is_unambiguous() {
+        assert!(!has_ambiguous_framing(&request("POST /articles HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}")));
+        assert!(!has_ambiguous_framing(&request("GET /articles HTTP/1.1\r\n\r\n")));
+    }
+
+    #[test]
+    fn repeated_equal_content_lengths_are_unambiguous() {
+        assert!(!has_ambiguous_framing(&request("POST /articles HTTP/1.1\r\nContent-Length: 2\r\ncontent-length: 2\r\n\r\n{}")));
+        assert!(!has_ambiguous_framing(&request("POST /articles HTTP/1.1\r\nContent-Length: 2, 2\r\n\r\n{}")));
+    }
+
+    #[test]
+    fn conflicting_content_lengths_are_ambiguous() {
+        assert!(has_ambiguous_framing(&request("POST /articles HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\n{}")));
+        assert!(has_ambiguous_framing(&request("POST /articles HTTP/1.1\r\nContent-Length: 2, 3\r\n\r\n{}")));
+        assert!(has_ambiguous_framing(&request("POST /articles HTTP/1.1\r\nContent-Length: two\r\n\r\n{}")));
+    }
+
+    #[test]
+    fn content_length_with_transfer_encoding_is_ambiguous() {
+        assert!(has_ambiguous_framing(&request(
+            "POST /articles HTTP/1.1\r\nContent-Length: 2\r\nTransfer-Encoding: chunked\r\n\r\n{}"
+        )));
+    }
+
+    #[test]
+    fn encrypted_content_round_trips() {
+        let config = config_with_key();
+        let stored = encrypt_content(&config, "secret article body");
+
+        assert!(stored.starts_with(ENCRYPTED_PREFIX));
+        assert!(!stored.contains("secret article body"));
+        assert_eq!(decrypt_content(&config, stored), "secret article body");
+    }
+
+    #[test]
+    fn each_encryption_uses_a_fresh_nonce() {
+        let config = config_with_key();
+        assert_ne!(encrypt_content(&config, "same"), encrypt_content(&config, "same"));
+    }
+
+    #[test]
+    fn content_is_plaintext_without_a_key() {
+        let config = test_config();
+        assert_eq!(encrypt_content(&config, "plain body"), "plain body");
+        assert_eq!(decrypt_content(&config, "plain body".to_string()), "plain body");
+    }
+
+    #[test]
+    fn plaintext_rows_pass_through_with_a_key() {
+        let config = config_with_key();
+        assert_eq!(decrypt_content(&config, "stored before encryption".to_string()), "stored before encryption");
+    }
+
+    #[test]
+    fn tampered_payload_is_returned_as_stored() {
+        let config = config_with_key();
+        let stored = encrypt_content(&config, "secret article body");
+        let mut payload = BASE64.decode(stored.strip_prefix(ENCRYPTED_PREFIX).unwrap()).unwrap();
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        let tampered = format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(payload));
+
+        assert_eq!(decrypt_content(&config, tampered.clone()), tampered);
+        assert_eq!(decrypt_content(&config, "enc:v1:not base64!".to_string()), "enc:v1:not base64!");
+    }
+
+    #[test]
+    fn compressed_and_encrypted_content_round_trips() {
+        let mut config = config_with_key();
+        config.compress_content = true;
+        let body = "repeated words ".repeat(50);
+
+        assert_eq!(decrypt_content(&config, encrypt_content(&config, &body)), body);
+    }
+
+    fn allowed_methods(path: &str) -> Option<&'static str> {
+        let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+        get_allowed_methods(&request(&raw).segments)
+    }
+
+    #[test]
+    fn known_routes_have_allowed_methods() {
+        assert_eq!(allowed_methods("/articles"), Some("GET, POST, DELETE, OPTIONS"));
+        assert_eq!(allowed_methods("/articles/5"), Some("GET, PUT, PATCH, DELETE, OPTIONS"));
+        assert_eq!(allowed_methods("/articles/5/move/6"), Some("POST, OPTIONS"));
+        assert_eq!(allowed_methods("/articles.rss"), Some("GET, OPTIONS"));
+    }
+
+    #[test]
+    fn look_alike_paths_are_not_routes() {
+        for path in [
+            "/articles-archive",
+            "/articlesx",
+            "/articles.json",
+            "/article",
+            "/articles/5/moves/6",
+            "/articles/5/move",
+            "/articles/5/exists/extra",
+            "/ping/extra",
+            "/pings",
+            "/admin",
+            "/admin/statusx",
+            "/scrape",
+        ] {
+            assert_eq!(allowed_methods(path), None, "{} should not match a route", path);
+        }
+    }
+
+    #[test]
+    fn look_alike_prefix_is_one_segment() {
+        assert_eq!(request("GET /articles-archive/5 HTTP/1.1\r\n\r\n").segments, ["articles-archive", "5"]);
+    }
+
+    fn rule(keyword: &str, category: &str) -> CategoryRule {
+        CategoryRule { id: None, keyword: keyword.to_string(), category: category.to_string() }
+    }
+
+    #[test]
+    fn keywords_match_whole_words_only() {
+        assert!(contains_word("why rust wins", "rust"));
+        assert!(contains_word("rust", "rust"));
+        assert!(contains_word("written in rust.", "rust"));
+        assert!(!contains_word("trusty tools", "rust"));
+        assert!(!contains_word("rusty nails", "rust"));
+        assert!(contains_word("trusty tools, and rust", "rust"));
+        assert!(!contains_word("anything", ""));
+    }
+
+    #[test]
+    fn phrases_match_across_words() {
+        assert!(contains_word("the world cup final", "world cup"));
+        assert!(!contains_word("the world cupboard", "world cup"));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = [rule("rust", "programming"), rule("zebra", "animals"), rule("wins", "sports")];
+
+        assert_eq!(match_category(&rules, "Why Rust wins", "").as_deref(), Some("programming"));
+        assert_eq!(match_category(&rules, "Untitled", "A ZEBRA escaped").as_deref(), Some("animals"));
+        assert_eq!(match_category(&rules, "Trusty tools", "nothing here").as_deref(), None);
+    }
+
+    #[test]
+    fn read_only_posts_are_not_writes() {
+        assert!(!is_write_request(&request("POST /articles/batch-get HTTP/1.1\r\n\r\n")));
+        assert!(!is_write_request(&request("POST /scrape/test HTTP/1.1\r\n\r\n")));
+        assert!(!is_write_request(&request("POST /debug/echo HTTP/1.1\r\n\r\n")));
+        assert!(!is_write_request(&request("GET /articles HTTP/1.1\r\n\r\n")));
+    }
+
+    #[test]
+    fn data_changing_methods_are_writes() {
+        assert!(is_write_request(&request("POST /articles HTTP/1.1\r\n\r\n")));
+        assert!(is_write_request(&request("POST /articles/bulk HTTP/1.1\r\n\r\n")));
+        assert!(is_write_request(&request("PUT /articles/1 HTTP/1.1\r\n\r\n")));
+        assert!(is_write_request(&request("DELETE /articles/batch-get HTTP/1.1\r\n\r\n")));
+    }
+
+    #[test]
+    fn source_from_path_decodes_and_normalizes() {
+        let mut config = test_config();
+        config.normalize_source = true;
+        assert_eq!(source_from_path(&config, "New%20York%20Times"), "new york times");
+        assert_eq!(source_from_path(&config, "imdb"), "imdb");
+
+        config.normalize_source = false;
+        assert_eq!(source_from_path(&config, "New%20York%20Times"), "New York Times");
+    }
+
+    #[test]
+    fn rate_limit_key_groups_ipv6_by_prefix() {
+        let key = |addr: &str| rate_limit_key(addr.parse().unwrap());
+        assert_eq!(key("2001:db8:1:2:aaaa::1"), key("2001:db8:1:2:bbbb::2"));
+        assert_eq!(key("2001:db8:1:2:aaaa::1"), "2001:db8:1:2::".parse::<IpAddr>().unwrap());
+        assert_ne!(key("2001:db8:1:2::1"), key("2001:db8:1:3::1"));
+    }
+
+    #[test]
+    fn rate_limit_key_keeps_ipv4_addresses() {
+        let key = |addr: &str| rate_limit_key(addr.parse().unwrap());
+        assert_eq!(key("203.0.113.7"), "203.0.113.7".parse::<IpAddr>().unwrap());
+        assert_ne!(key("203.0.113.7"), key("203.0.113.8"));
+        assert_eq!(key("::ffff:203.0.113.7"), "203.0.113.7".parse::<IpAddr>().unwrap());
+    }
+
+    fn article(title: &str, source: &str) -> Article {
+        serde_json::from_value(serde_json::json!({ "title": title, "content": "content", "source": source })).unwrap()
+    }
+
+    // Answers every statement with no rows, for driving query paths without a database
+    struct NoRows;
+
+    impl Queries for NoRows {
+        fn query(&mut self, _: &str, _: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PostgresError> {
+            Ok(Vec::new())
+        }
+        fn query_one(&mut self, _: &str, _: &[&(dyn ToSql + Sync)]) -> Result<Row, PostgresError> {
+            unreachable!("NoRows has no row to return")
+        }
+        fn query_opt(&mut self, _: &str, _: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PostgresError> {
+            Ok(None)
+        }
+        fn execute(&mut self, _: &str, _: &[&(dyn ToSql + Sync)]) -> Result<u64, PostgresError> {
+            Ok(0)
+        }
+        fn batch_execute(&mut self, _: &str) -> Result<(), PostgresError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn query_budget_warns_when_an_import_queries_per_article() {
+        let mut config = test_config();
+        let r = request("POST /articles/import-from-url HTTP/1.1\r\n\r\n");
+        let articles = (1..=3).map(|n| article(&format!("Title {}", n), "remote")).collect();
+
+        db::reset_query_count();
+        insert_new_articles(&mut db::Counted::new(NoRows), &config, articles).unwrap();
+        assert_eq!(db::query_count(), 3);
+
+        config.query_budget = Some(3);
+        assert_eq!(query_budget_warning(&config, &r, db::query_count()), None);
+        config.query_budget = Some(2);
+        assert_eq!(
+            query_budget_warning(&config, &r, db::query_count()).as_deref(),
+            Some("POST /articles/import-from-url ran 3 queries, over the QUERY_BUDGET of 2")
+        );
+    }
+
+    #[test]
+    fn query_budget_is_off_by_default() {
+        let config = test_config();
+        let r = request("GET /articles HTTP/1.1\r\n\r\n");
+        assert_eq!(query_budget_warning(&config, &r, 100), None);
+    }
+
+    #[test]
+    fn empty_or_whitespace_title_and_source_are_rejected() {
+        let config = test_config();
+        assert_eq!(validate_article(&article("", "imdb"), &config), Err("title must not be empty".to_string()));
+        assert_eq!(validate_article(&article(" \t\n", "imdb"), &config), Err("title must not be empty".to_string()));
+        assert_eq!(validate_article(&article("Title", ""), &config), Err("source must not be empty".to_string()));
+        assert_eq!(validate_article(&article("Title", "   "), &config), Err("source must not be empty".to_string()));
+    }
+
+    #[test]
+    fn article_with_title_and_source_is_valid() {
+        let config = test_config();
+        assert_eq!(validate_article(&article("Title", "imdb"), &config), Ok(()));
+        assert_eq!(validate_article(&article("  Padded title ", " imdb "), &config), Ok(()));
+    }
+
+    #[test]
+    fn url_variants_normalize_to_the_same_value() {
+        let config = test_config();
+        let canonical = normalize_url(&config, Some("https://example.com/news/story?id=7"));
+        assert_eq!(canonical.as_deref(), Some("https://example.com/news/story?id=7"));
+
+        for variant in [
+            "https://example.com/news/story?id=7&utm_source=feed&fbclid=abc",
+            "https://example.com/news/story?id=7#comments",
+            "https://example.com/news/story/?id=7",
+            "HTTPS://Example.COM/news/story?id=7",
+            "  https://EXAMPLE.com/news/story/?utm_medium=email&id=7#top ",
+        ] {
+            assert_eq!(normalize_url(&config, Some(variant)), canonical, "{}", variant);
+        }
+    }
+
+    #[test]
+    fn url_without_a_path_or_kept_params_keeps_the_root() {
+        let config = test_config();
+        assert_eq!(normalize_url(&config, Some("https://example.com/?utm_source=x")).as_deref(), Some("https://example.com/"));
+        assert_eq!(normalize_url(&config, Some("not a url")), None);
+        assert_eq!(normalize_url(&config, None), None);
+    }
+
+    #[test]
+    fn scraped_titles_below_the_minimum_are_dropped() {
+        let articles = ["", " ", "A", " Ab ", "Abc", "Ünï"].into_iter().map(|title| article(title, "imdb")).collect();
+        let kept: Vec<String> = drop_short_titles(articles, 3).into_iter().map(|article| article.title).collect();
+        assert_eq!(kept, ["Abc", "Ünï"]);
+    }
+
+    #[test]
+    fn scraped_titles_at_the_minimum_are_kept() {
+        let articles = vec![article("Ab", "imdb"), article(" Ab ", "imdb"), article("A", "imdb")];
+        let kept: Vec<String> = drop_short_titles(articles, 2).into_iter().map(|article| article.title).collect();
+        assert_eq!(kept, ["Ab", " Ab "]);
+    }
+
+    #[test]
+    fn normalize_source_trims_and_folds_case() {
+        assert_eq!(normalize_source("  IMDb \t"), "imdb");
+        assert_eq!(normalize_source("New York Times"), "new york times");
+        assert_eq!(normalize_source("ÉLYSÉE"), "élysée");
+        assert_eq!(normalize_source("imdb"), "imdb");
+    }
+
+    #[test]
+    fn escape_xml_escapes_markup_characters() {
+        assert_eq!(escape_xml(r#"Tom & "Jerry" <b>'s</b>"#), "Tom &amp; &quot;Jerry&quot; &lt;b&gt;&apos;s&lt;/b&gt;");
+        assert_eq!(escape_xml("&amp;"), "&amp;amp;");
+        assert_eq!(escape_xml("plain"), "plain");
+    }
+
+    #[test]
+    fn rss_items_escape_title_content_link_and_source() {
+        let mut item = article("AT&T <launch>", r#"Bob's "feed""#);
+        item.content = "1 < 2 & 3 > 2".to_string();
+        item.url = Some("https://example.com/?a=1&b='2'".to_string());
+        let rss = render_rss(&[item], Some("localhost"), Some(r#"Bob's "feed""#));
+
+        assert!(rss.contains("<title>AT&amp;T &lt;launch&gt;</title>"));
+        assert!(rss.contains("<description>1 &lt; 2 &amp; 3 &gt; 2</description>"));
+        assert!(rss.contains("<link>https://example.com/?a=1&amp;b=&apos;2&apos;</link>"));
+        assert!(rss.contains("<title>newsapi: Bob&apos;s &quot;feed&quot;</title>"));
+        assert!(!rss.contains("<launch>"));
+    }
+
+    #[test]
+    fn escape_like_makes_wildcards_literal() {
+        assert_eq!(escape_like("100%"), "100\\%");
+        assert_eq!(escape_like("snake_case"), "snake\\_case");
+        assert_eq!(escape_like("a\\b%_"), "a\\\\b\\%\\_");
+        assert_eq!(escape_like("plain words"), "plain words");
+    }
+
+    #[test]
+    fn pagination_links_on_first_middle_and_last_page() {
+        let r = request("GET /articles?source=imdb&limit=10&offset=0 HTTP/1.1\r\n\r\n");
+        assert_eq!(get_pagination_links(&r, 10, 0, 25), "</articles?source=imdb&limit=10&offset=10>; rel=\"next\"");
+        assert_eq!(
+            get_pagination_links(&r, 10, 10, 25),
+            "</articles?source=imdb&limit=10&offset=20>; rel=\"next\", </articles?source=imdb&limit=10&offset=0>; rel=\"prev\""
+        );
+        assert_eq!(get_pagination_links(&r, 10, 20, 25), "</articles?source=imdb&limit=10&offset=10>; rel=\"prev\"");
+    }
+
+    #[test]
+    fn pagination_links_are_empty_for_a_single_page() {
+        let r = request("GET /articles HTTP/1.1\r\n\r\n");
+        assert_eq!(get_pagination_links(&r, 10, 0, 10), "");
+        assert_eq!(get_pagination_links(&r, 10, 0, 0), "");
+        assert_eq!(get_pagination_links(&r, 10, 5, 12), "</articles?limit=10&offset=0>; rel=\"prev\"");
+    }
+
+    fn canonical_location(target: &str) -> Option<String> {
+        let raw = format!("GET {} HTTP/1.1\r\n\r\n", target);
+        get_canonical_location(&request(&raw))
+    }
+
+    #[test]
+    fn trailing_and_double_slashes_redirect_to_the_canonical_path() {
+        assert_eq!(canonical_location("/articles/").as_deref(), Some("/articles"));
+        assert_eq!(canonical_location("/articles//5").as_deref(), Some("/articles/5"));
+        assert_eq!(canonical_location("//articles///5//?limit=2").as_deref(), Some("/articles/5?limit=2"));
+        assert_eq!(canonical_location("/Articles/5/").as_deref(), Some("/articles/5"));
+        assert_eq!(canonical_location("//").as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn canonical_paths_are_not_redirected() {
+        assert_eq!(canonical_location("/articles/5?limit=2"), None);
+        assert_eq!(canonical_location("/"), None);
+    }
+
+    fn if_none_match(value: &str) -> String {
+        format!("GET /articles HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", value)
+    }
+
+    #[test]
+    fn etag_is_weak_and_stable_for_a_body() {
+        let etag = get_etag("[]");
+        assert!(etag.starts_with("W/\"") && etag.ends_with('"'));
+        assert_eq!(etag, get_etag("[]"));
+        assert_ne!(etag, get_etag("[{}]"));
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        let etag = get_etag("[]");
+        let strong = etag.trim_start_matches("W/").to_string();
+        assert!(etag_matches(&request(&if_none_match(&etag)), &etag));
+        assert!(etag_matches(&request(&if_none_match(&strong)), &etag));
+        assert!(!etag_matches(&request(&if_none_match("W/\"0000000000000000\"")), &etag));
+        assert!(!etag_matches(&request("GET /articles HTTP/1.1\r\n\r\n"), &etag));
+    }
+
+    #[test]
+    fn if_none_match_accepts_a_list_or_star() {
+        let etag = get_etag("[]");
+        let list = format!("\"stale\", {} ,W/\"other\"", etag);
+        assert!(etag_matches(&request(&if_none_match(&list)), &etag));
+        assert!(!etag_matches(&request(&if_none_match("\"stale\", W/\"other\"")), &etag));
+        assert!(etag_matches(&request(&if_none_match("*")), &etag));
+    }
+
+    fn accept_encoding(value: &str) -> bool {
+        accepts_gzip(&request(&format!("GET /articles HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n", value)))
+    }
+
+    #[test]
+    fn gzip_is_accepted_when_listed_or_starred() {
+        assert!(accept_encoding("gzip"));
+        assert!(accept_encoding("br, GZIP;q=0.5"));
+        assert!(accept_encoding("deflate, *"));
+        assert!(!accept_encoding("br, deflate"));
+        assert!(!accepts_gzip(&request("GET /articles HTTP/1.1\r\n\r\n")));
+    }
+
+    #[test]
+    fn gzip_with_q_zero_is_refused() {
+        assert!(!accept_encoding("gzip;q=0"));
+        assert!(!accept_encoding("gzip; Q=0.000"));
+        assert!(!accept_encoding("*;q=0"));
+        assert!(!accept_encoding("gzip;q=0, *"));
+        assert!(!accept_encoding("*, gzip;q=0"));
+    }
+
+    fn accept(value: &str) -> bool {
+        prefers_plain_text(&request(&format!("GET /articles HTTP/1.1\r\nAccept: {}\r\n\r\n", value)))
+    }
+
+    #[test]
+    fn plain_text_wins_when_weighted_above_json() {
+        assert!(accept("text/plain"));
+        assert!(accept("application/json;q=0.5, text/plain"));
+        assert!(accept("text/plain;q=0.9, application/json;q=0.8"));
+        assert!(accept("text/plain, application/json"));
+    }
+
+    #[test]
+    fn json_wins_when_weighted_above_plain_text() {
+        assert!(!accept("text/plain;q=0.5, application/json"));
+        assert!(!accept("application/json, text/plain"));
+        assert!(!accept("text/plain;q=0"));
+        assert!(!accept("*/*"));
+        assert!(!prefers_plain_text(&request("GET /articles HTTP/1.1\r\n\r\n")));
+    }
+
+    #[test]
+    fn rate_bucket_is_exhausted_after_the_limit() {
+        let start = Instant::now();
+        let mut bucket = RateBucket { tokens: 3.0, updated: start };
+
+        assert_eq!(bucket.take(3.0, start), Ok(()));
+        assert_eq!(bucket.take(3.0, start), Ok(()));
+        assert_eq!(bucket.take(3.0, start), Ok(()));
+        assert_eq!(bucket.take(3.0, start), Err(20));
+    }
+
+    #[test]
+    fn rate_bucket_refills_over_time_up_to_the_limit() {
+        let start = Instant::now();
+        let mut bucket = RateBucket { tokens: 0.0, updated: start };
+
+        assert_eq!(bucket.take(60.0, start + Duration::from_millis(500)), Err(1));
+        assert_eq!(bucket.take(60.0, start + Duration::from_secs(1)), Ok(()));
+        assert_eq!(bucket.take(60.0, start + Duration::from_secs(1)), Err(1));
+
+        bucket.take(60.0, start + Duration::from_secs(3600)).unwrap();
+        assert_eq!(bucket.tokens, 59.0);
+    }
+
+    fn with_query_params(count: usize) -> String {
+        let query: Vec<String> = (0..count).map(|n| format!("p{}={}", n, n)).collect();
+        format!("GET /articles?{} HTTP/1.1\r\n\r\n", query.join("&"))
+    }
+
+    #[test]
+    fn exactly_max_query_params_are_allowed() {
+        let mut config = test_config();
+        config.max_query_params = 5;
+        assert!(!has_too_many_query_params(&request(&with_query_params(5)), &config));
+        assert!(!has_too_many_query_params(&request("GET /articles?a=1&&b=2&&&c=3&d&e= HTTP/1.1\r\n\r\n"), &config));
+    }
+
+    #[test]
+    fn one_more_than_max_query_params_is_rejected() {
+        let mut config = test_config();
+        config.max_query_params = 5;
+        assert!(has_too_many_query_params(&request(&with_query_params(6)), &config));
+        assert!(has_too_many_query_params(&request("GET /articles?a&a&a&a&a&a HTTP/1.1\r\n\r\n"), &config));
+    }
+
+    // Hands out one chunk per read, like a socket receiving a request in pieces
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0);
+            buffer[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn request_split_across_reads_is_read_whole() {
+        let config = test_config();
+        let mut stream = Chunks(vec![b"POST /articles HTTP/1.1\r\nContent-", b"Length: 11\r\n\r", b"\n{\"ti", b"tle\"", b":1}"]);
+        let mut pending = Vec::new();
+
+        let raw = read_request(&mut stream, &mut pending, &config).unwrap().unwrap();
+        assert_eq!(raw, "POST /articles HTTP/1.1\r\nContent-Length: 11\r\n\r\n{\"title\":1}");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn bytes_past_the_body_are_kept_for_the_next_request() {
+        let config = test_config();
+        let mut stream = Chunks(vec![b"POST /a HTTP/1.1\r\nContent-Length: 2\r\n\r\n{", b"}GET /b HTTP/1.1\r\n", b"\r\n"]);
+        let mut pending = Vec::new();
+
+        let first = read_request(&mut stream, &mut pending, &config).unwrap().unwrap();
+        assert_eq!(first, "POST /a HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}");
+        assert_eq!(pending, b"GET /b HTTP/1.1\r\n");
+
+        let second = read_request(&mut stream, &mut pending, &config).unwrap().unwrap();
+        assert_eq!(second, "GET /b HTTP/1.1\r\n\r\n");
+        assert_eq!(read_request(&mut stream, &mut pending, &config).unwrap().unwrap(), "");
+    }
+
+    #[test]
+    fn oversized_body_is_refused_before_it_is_read() {
+        let mut config = test_config();
+        config.max_body_bytes = 4;
+        let mut stream = Chunks(vec![b"POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\n", b"12345"]);
+
+        let (status_line, _) = read_request(&mut stream, &mut Vec::new(), &config).unwrap().unwrap_err();
+        assert!(status_line.starts_with("HTTP/1.1 413"));
+        assert_eq!(stream.0, [b"12345"]);
+    }
+
+    #[test]
+    fn message_response_is_json() {
+        let (status_line, body) = message_response("Article \"5\" deleted");
+        assert!(status_line.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n"));
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap(), serde_json::json!({ "message": "Article \"5\" deleted" }));
+    }
+
+    #[test]
+    fn route_slots_are_capped_and_given_back() {
+        let slots = RouteSlots::new(Some(2));
+        let first = slots.try_claim().unwrap();
+        let _second = slots.try_claim().unwrap();
+        assert!(slots.try_claim().is_none());
+
+        drop(first);
+        assert!(slots.try_claim().is_some());
+        assert_eq!(slots.running.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn route_slots_without_a_limit_never_run_out() {
+        let slots = RouteSlots::new(None);
+        let claimed: Vec<RouteSlot> = (0..100).filter_map(|_| slots.try_claim()).collect();
+        assert_eq!(claimed.len(), 100);
+    }
+
+    #[test]
+    fn concurrent_exports_beyond_the_cap_are_turned_away() {
+        let slots = RouteSlots::new(Some(2));
+        let ready = Arc::new(std::sync::Barrier::new(8));
+        let done = Arc::new(std::sync::Barrier::new(8));
+
+        let exports: Vec<_> = (0..8)
+            .map(|_| {
+                let (slots, ready, done) = (Arc::clone(&slots), Arc::clone(&ready), Arc::clone(&done));
+                thread::spawn(move || {
+                    ready.wait();
+                    // Each claimed slot is held until every export has tried, like a long-running export
+                    let slot = slots.try_claim();
+                    done.wait();
+                    slot.is_some()
+                })
+            })
+            .collect();
+        let started = exports.into_iter().map(|export| export.join().unwrap()).filter(|&started| started).count();
+
+        assert_eq!(started, 2);
+        assert_eq!(slots.running.load(Ordering::SeqCst), 0);
+        let (status_line, body) = slots.busy_response("exports");
+        assert!(status_line.starts_with("HTTP/1.1 503 SERVICE UNAVAILABLE\r\nRetry-After: 1\r\n"));
+        assert!(body.contains("Too many exports running (limit 2)"));
+    }
+
+    #[test]
+    fn keep_alive_stops_at_the_max_requests() {
+        let mut config = test_config();
+        config.keep_alive_max_requests = 3;
+        let r = request("GET /ping HTTP/1.1\r\n\r\n");
+
+        assert!(wants_keep_alive(&r, &config, 1));
+        assert!(wants_keep_alive(&r, &config, 2));
+        assert!(!wants_keep_alive(&r, &config, 3));
+    }
+
+    // A connection that sends the given bytes and records everything written back
+    struct Pipe {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buffer)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.output.write(buffer)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // AppState around a pool that never connects, for routes that don't touch the database
+    fn test_state(config: Config) -> Arc<AppState> {
+        let manager = PostgresConnectionManager::new(config.db.clone(), NoTls);
+        Arc::new(AppState::new(config, DbPool::new(Pool::builder().build_unchecked(manager))))
+    }
+
+    #[test]
+    fn keep_alive_connection_is_closed_after_the_max_requests() {
+        let mut config = test_config();
+        config.keep_alive_max_requests = 3;
+        let mut pipe = Pipe { input: io::Cursor::new("GET /ping HTTP/1.1\r\n\r\n".repeat(5).into_bytes()), output: Vec::new() };
+
+        handle_client(&mut pipe, "127.0.0.1".parse().unwrap(), &test_state(config));
+
+        let output = String::from_utf8(pipe.output).unwrap();
+        assert_eq!(output.matches("pong").count(), 3);
+        assert_eq!(output.matches("Connection: keep-alive\r\n").count(), 2);
+        assert!(output.rsplit("HTTP/1.1 200 OK").next().unwrap().contains("Connection: close\r\n"));
+    }
+
+    fn db_max_lifetime(value: &str) -> Option<u64> {
+        Config::from_vars(&|name| match name {
+            "DATABASE_URL" => Ok("postgres://postgres@localhost/newsapi_test".to_string()),
+            "DB_MAX_LIFETIME_SECS" => Ok(value.to_string()),
+            _ => Err(env::VarError::NotPresent),
+        })
+        .db_max_lifetime_secs
+    }
+
+    #[test]
+    fn pooled_connections_are_replaced_after_half_an_hour_by_default() {
+        assert_eq!(test_config().db_max_lifetime_secs, Some(1800));
+        assert_eq!(db_max_lifetime(" 300 "), Some(300));
+        assert_eq!(db_max_lifetime("soon"), Some(1800));
+    }
+
+    #[test]
+    fn zero_db_max_lifetime_keeps_connections() {
+        assert_eq!(db_max_lifetime("0"), None);
+    }
 }
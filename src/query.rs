@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::fmt;
+
+// Error for a query parameter that is present but malformed; handlers answer these with a 400
+#[derive(Debug)]
+pub struct QueryError(String);
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// QueryParams: the decoded query string of a request, keyed by parameter name.
+// Repeated keys keep every value in the order they appeared.
+pub struct QueryParams {
+    params: HashMap<String, Vec<String>>,
+}
+
+impl QueryParams {
+    // Number of parameters in a query string, counted without decoding anything
+    pub fn count(query: &str) -> usize {
+        query.split('&').filter(|pair| !pair.is_empty()).count()
+    }
+
+    // Parse a query string like "a=1&b=x%20y&flag"
+    pub fn parse(query: &str) -> QueryParams {
+        let mut params: HashMap<String, Vec<String>> = HashMap::new();
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            params.entry(url_decode(key)).or_default().push(url_decode(value));
+        }
+
+        QueryParams { params }
+    }
+
+    // Every parameter with all its values, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.params.iter().map(|(name, values)| (name.as_str(), values.as_slice()))
+    }
+
+    // First value for a parameter, if present
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.params.get(name).and_then(|values| values.first()).map(String::as_str)
+    }
+
+    // Integer parameter; a present but non-numeric value is an error
+    pub fn get_i64(&self, name: &str) -> Result<Option<i64>, QueryError> {
+        match self.get_str(name) {
+            None => Ok(None),
+            Some(value) => value
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| QueryError(format!("Query parameter '{}' must be an integer", name))),
+        }
+    }
+
+    // Boolean parameter accepting true/false, 1/0 and yes/no; a bare "?flag" counts as true
+    pub fn get_bool(&self, name: &str) -> Result<Option<bool>, QueryError> {
+        match self.get_str(name).map(|value| value.trim().to_lowercase()) {
+            None => Ok(None),
+            Some(value) => match value.as_str() {
+                "" | "true" | "1" | "yes" => Ok(Some(true)),
+                "false" | "0" | "no" => Ok(Some(false)),
+                _ => Err(QueryError(format!("Query parameter '{}' must be true or false", name))),
+            },
+        }
+    }
+
+    // Boolean parameter that defaults to false when absent
+    pub fn get_flag(&self, name: &str) -> Result<bool, QueryError> {
+        Ok(self.get_bool(name)?.unwrap_or(false))
+    }
+
+    // List parameter from comma-separated and/or repeated values, e.g. "?id=1,2&id=3"
+    pub fn get_list(&self, name: &str) -> Vec<String> {
+        self.params
+            .get(name)
+            .map(|values| {
+                values
+                    .iter()
+                    .flat_map(|value| value.split(','))
+                    .map(|item| item.trim().to_string())
+                    .filter(|item| !item.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+// Decode a form-urlencoded component: "+" becomes a space and %XX escapes become bytes.
+// Malformed escapes are kept literally.
+pub fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            // Both characters must be hex digits; from_str_radix alone would take a sign, as in "%+1"
+            b'%' if i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap_or_default());
+                i += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_parameters_are_none() {
+        let params = QueryParams::parse("limit=5");
+        assert_eq!(params.get_str("offset"), None);
+        assert_eq!(params.get_i64("offset").unwrap(), None);
+        assert_eq!(params.get_bool("include_raw").unwrap(), None);
+        assert!(!params.get_flag("include_raw").unwrap());
+        assert!(params.get_list("id").is_empty());
+    }
+
+    #[test]
+    fn parses_typed_values() {
+        let params = QueryParams::parse("limit=%2010&raw=yes&off=0&flag");
+        assert_eq!(params.get_i64("limit").unwrap(), Some(10));
+        assert_eq!(params.get_bool("raw").unwrap(), Some(true));
+        assert_eq!(params.get_bool("off").unwrap(), Some(false));
+        assert!(params.get_flag("flag").unwrap());
+    }
+
+    #[test]
+    fn malformed_values_are_errors() {
+        let params = QueryParams::parse("limit=ten&raw=maybe");
+        assert_eq!(params.get_i64("limit").unwrap_err().to_string(), "Query parameter 'limit' must be an integer");
+        assert_eq!(params.get_bool("raw").unwrap_err().to_string(), "Query parameter 'raw' must be true or false");
+        assert!(params.get_flag("raw").is_err());
+    }
+
+    #[test]
+    fn repeated_keys_keep_the_first_value() {
+        let params = QueryParams::parse("source=a&source=b");
+        assert_eq!(params.get_str("source"), Some("a"));
+    }
+
+    #[test]
+    fn lists_combine_commas_and_repeats() {
+        let params = QueryParams::parse("id=1,2&id=3&id=&tag=%20x%20,,y");
+        assert_eq!(params.get_list("id"), ["1", "2", "3"]);
+        assert_eq!(params.get_list("tag"), ["x", "y"]);
+    }
+
+    #[test]
+    fn counts_pairs_without_decoding() {
+        assert_eq!(QueryParams::count(""), 0);
+        assert_eq!(QueryParams::count("a=1&&b&c=%26"), 3);
+    }
+
+    #[test]
+    fn decodes_escapes_and_plus() {
+        assert_eq!(url_decode("a+b%20c%2Fd"), "a b c/d");
+        assert_eq!(url_decode("100%"), "100%");
+        assert_eq!(url_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn escapes_need_two_hex_digits() {
+        assert_eq!(url_decode("%+1"), "% 1");
+        assert_eq!(url_decode("%-1x"), "%-1x");
+        assert_eq!(url_decode("%4"), "%4");
+        assert_eq!(url_decode("%4g%41"), "%4gA");
+        assert_eq!(url_decode("%C3%a9"), "é");
+    }
+}
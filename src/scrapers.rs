@@ -0,0 +1,203 @@
+use std::fmt;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+
+use crate::{Article, Config};
+
+// Number of list items a scrape takes when no ?count is given, and the most it will take
+pub const DEFAULT_SCRAPE_COUNT: usize = 10;
+pub const MAX_SCRAPE_COUNT: usize = 100;
+
+// Error from parsing a source page; scrape handlers answer these with a 500
+#[derive(Debug)]
+pub struct ScrapeError(String);
+
+impl fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Scraper: one source that can be scraped. Fetching the page, storing raw HTML and inserting the
+// articles are shared, so a source only names itself, points at its list page and parses it.
+pub trait Scraper: Sync {
+    // Name used in /scrape/:source and stored as the article source
+    fn source(&self) -> &'static str;
+
+    // List page, used as the base for resolving links
+    fn url(&self) -> &'static str;
+
+    // List page to fetch for up to count items; sources whose page size is fixed use url() as is
+    fn list_url(&self, _count: usize) -> String {
+        self.url().to_string()
+    }
+
+    // Category given to this source's articles that no AUTO_CATEGORIZE rule matched
+    fn default_category(&self) -> Option<&'static str> {
+        None
+    }
+
+    // Turn the fetched page into at most count articles
+    fn parse(&self, document: &Html, config: &Config, count: usize) -> Result<Vec<Article>, ScrapeError>;
+
+    // Resolve a link found on the list page against the page URL
+    fn page_url(&self, href: &str) -> Option<String> {
+        Url::parse(self.url()).ok()?.join(href).ok().map(String::from)
+    }
+}
+
+// Registered scrapers; add new sources here
+static SCRAPERS: &[&dyn Scraper] = &[&ImdbScraper];
+
+// Every registered scraper, in registration order
+pub fn all_scrapers() -> &'static [&'static dyn Scraper] {
+    SCRAPERS
+}
+
+// Look up a scraper by source name
+pub fn find_scraper(source: &str) -> Option<&'static dyn Scraper> {
+    SCRAPERS.iter().copied().find(|scraper| scraper.source() == source)
+}
+
+// ImdbScraper: the IMDb top 100 list, sorted by rating
+pub struct ImdbScraper;
+
+impl Scraper for ImdbScraper {
+    fn source(&self) -> &'static str {
+        "imdb"
+    }
+
+    fn url(&self) -> &'static str {
+        "https://www.imdb.com/search/title/?groups=top_100&sort=user_rating,desc"
+    }
+
+    fn list_url(&self, count: usize) -> String {
+        format!("{}&count={}", self.url(), count)
+    }
+
+    fn default_category(&self) -> Option<&'static str> {
+        Some("movie")
+    }
+
+    fn parse(&self, document: &Html, config: &Config, count: usize) -> Result<Vec<Article>, ScrapeError> {
+        let item_selector = parse_selector("div.lister-item")?;
+        let title_selector = parse_selector("h3.lister-item-header>a")?;
+        let image_selector = parse_selector(".lister-item-image img")?;
+        let dates = DateExtractor::from_config(config);
+
+        // Each list item gives a title and, when ARTICLE_IMAGES is on, its poster (lazy-loaded via loadlate)
+        let articles = document
+            .select(&item_selector)
+            .filter_map(|item| {
+                let anchor = item.select(&title_selector).next()?;
+                let title = anchor.inner_html();
+                let url = anchor.value().attr("href").and_then(|href| self.page_url(href));
+                let image_url = item
+                    .select(&image_selector)
+                    .next()
+                    .and_then(|img| img.value().attr("loadlate").or(img.value().attr("src")))
+                    .filter(|_| config.article_images)
+                    .map(str::to_string);
+                Some((title, url, image_url, dates.as_ref().and_then(|dates| dates.extract(item))))
+            })
+            .take(count)
+            .zip(1..)
+            .map(|((title, url, image_url, published_at), number)| Article {
+                id: None,
+                content: format!("{}. {}", number, title),
+                title,
+                source: self.source().to_string(),
+                raw_html: None,
+                position: None,
+                image_url,
+                url,
+                lat: None,
+                lon: None,
+                category: None,
+                created_at: None,
+                updated_at: None,
+                published_at,
+            })
+            .collect::<Vec<_>>();
+
+        if articles.len() < count {
+            log::warn!("asked IMDb for {} items but the page had {}", count, articles.len());
+        }
+
+        Ok(articles)
+    }
+}
+
+fn parse_selector(selector: &str) -> Result<Selector, ScrapeError> {
+    Selector::parse(selector).map_err(|e| ScrapeError(format!("Error creating selector {}: {}", selector, e)))
+}
+
+// Reads publication dates off list items; only built when both SCRAPE_DATE_SELECTOR and SCRAPE_DATE_FORMAT are set
+struct DateExtractor<'a> {
+    selector: Selector,
+    format: &'a str,
+}
+
+impl<'a> DateExtractor<'a> {
+    fn from_config(config: &'a Config) -> Option<DateExtractor<'a>> {
+        let (selector, format) = match (&config.scrape_date_selector, &config.scrape_date_format) {
+            (Some(selector), Some(format)) => (selector, format),
+            _ => return None,
+        };
+
+        match Selector::parse(selector) {
+            Ok(selector) => Some(DateExtractor { selector, format }),
+            Err(e) => {
+                log::warn!("ignoring invalid SCRAPE_DATE_SELECTOR: {}", e);
+                None
+            }
+        }
+    }
+
+    fn extract(&self, item: ElementRef) -> Option<DateTime<Utc>> {
+        let text = item.select(&self.selector).next()?.text().collect::<String>();
+        parse_published_at(text.trim(), self.format)
+    }
+}
+
+// Parse a scraped date with SCRAPE_DATE_FORMAT, accepting formats with or without a time and offset
+// (dates without an offset are taken as UTC); logs a warning and gives None when the text doesn't match
+fn parse_published_at(text: &str, format: &str) -> Option<DateTime<Utc>> {
+    let parsed = DateTime::parse_from_str(text, format)
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(text, format).map(|date| date.and_utc()))
+        .or_else(|_| NaiveDate::parse_from_str(text, format).map(|date| date.and_time(NaiveTime::MIN).and_utc()));
+
+    match parsed {
+        Ok(date) => Some(date),
+        Err(e) => {
+            log::warn!("could not parse publication date {:?} with {:?}: {}", text, format, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn parses_dates_with_offset_time_or_date_only() {
+        assert_eq!(parse_published_at("2024-03-05T10:30:00+02:00", "%Y-%m-%dT%H:%M:%S%z"), Some(utc("2024-03-05T08:30:00Z")));
+        assert_eq!(parse_published_at("2024-03-05 10:30", "%Y-%m-%d %H:%M"), Some(utc("2024-03-05T10:30:00Z")));
+        assert_eq!(parse_published_at("05 Mar 2024", "%d %b %Y"), Some(utc("2024-03-05T00:00:00Z")));
+    }
+
+    #[test]
+    fn dates_not_matching_the_format_are_none() {
+        assert_eq!(parse_published_at("March 5, 2024", "%Y-%m-%d"), None);
+        assert_eq!(parse_published_at("2024-13-40", "%Y-%m-%d"), None);
+        assert_eq!(parse_published_at("", "%Y-%m-%d"), None);
+        assert_eq!(parse_published_at("2024-03-05 junk", "%Y-%m-%d"), None);
+    }
+}